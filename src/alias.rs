@@ -4,15 +4,16 @@ use std::error::Error;
 use std::io::Write;
 #[cfg(target_os = "windows")]
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use std::os::unix::fs::symlink;
 
 use clap::ArgMatches;
-#[cfg(any(target_os = "macos", target_os = "linux"))]
+use regex::Regex;
 use simple_error::*;
 use simplelog::*;
+use tabular::*;
 
 #[cfg(target_os = "macos")]
 use crate::macos::*;
@@ -23,47 +24,37 @@ use crate::windows::*;
 #[cfg(target_os = "linux")]
 use crate::linux::*;
 
+use crate::common::*;
+use crate::config::*;
+use crate::error::*;
 use crate::escalate::*;
+use crate::output::*;
+use crate::rversion::Alias;
 
 #[cfg(target_os = "macos")]
-pub fn get_alias(args: &ArgMatches) -> Option<String> {
-    match args.value_of("str") {
-        None => None,
-        Some(str) => {
-            match str {
-                "oldrel" | "oldrel/1" => Some("oldrel".to_string()),
-                "release" | "devel" | "next" => Some(str.to_string()),
-                _ => None
-            }
-        }
+pub fn get_alias(str: &str) -> Option<String> {
+    match str {
+        "oldrel" | "oldrel/1" => Some("oldrel".to_string()),
+        "release" | "devel" | "next" => Some(str.to_string()),
+        _ => None
     }
 }
 
 #[cfg(target_os = "linux")]
-pub fn get_alias(args: &ArgMatches) -> Option<String> {
-    match args.value_of("str") {
-        None => None,
-        Some(str) => {
-            match str {
-                "oldrel" | "oldrel/1" => Some("oldrel".to_string()),
-                "release" => Some(str.to_string()),
-                _ => None
-            }
-        }
+pub fn get_alias(str: &str) -> Option<String> {
+    match str {
+        "oldrel" | "oldrel/1" => Some("oldrel".to_string()),
+        "release" => Some(str.to_string()),
+        _ => None
     }
 }
 
 #[cfg(target_os = "windows")]
-pub fn get_alias(args: &ArgMatches) -> Option<String> {
-    match args.value_of("str") {
-        None => None,
-        Some(str) => {
-            match str {
-                "oldrel" | "oldrel/1" => Some("oldrel".to_string()),
-                "release" | "next" => Some(str.to_string()),
-                _ => None
-            }
-        }
+pub fn get_alias(str: &str) -> Option<String> {
+    match str {
+        "oldrel" | "oldrel/1" => Some("oldrel".to_string()),
+        "release" | "next" => Some(str.to_string()),
+        _ => None
     }
 }
 
@@ -74,30 +65,39 @@ pub fn add_alias(ver: &str, alias: &str) -> Result<(), Box<dyn Error>> {
 
     info!("Adding R-{} alias to R {}", alias, ver);
 
-    let base = Path::new(R_ROOT);
-    let target = base.join(ver).join("Resources/bin/R");
-    let linkfile = Path::new("/usr/local/bin/").join("R-".to_string() + alias);
+    let bin = Path::new(R_ROOT).join(ver).join("Resources/bin");
+    for tool in get_quick_links()? {
+        let target = bin.join(&tool);
+        if !target.exists() {
+            debug!("No {} in R {}, no {}-{} alias", tool, ver, tool, alias);
+            continue;
+        }
+        let linkfile = Path::new("/usr/local/bin/").join(tool + "-" + alias);
+        add_alias_link(&target, &linkfile)?;
+    }
 
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn add_alias_link(target: &Path, linkfile: &Path) -> Result<(), Box<dyn Error>> {
     // If it exists then we check that it points to the right place
     // Cannot use .exists(), because it follows symlinks
-    let meta = std::fs::symlink_metadata(&linkfile);
+    let meta = std::fs::symlink_metadata(linkfile);
     if meta.is_ok() {
-        match std::fs::read_link(&linkfile) {
+        match std::fs::read_link(linkfile) {
             Err(_) => bail!("{} is not a symlink, aborting", linkfile.display()),
             Ok(xtarget) => {
                 if xtarget == target {
                     return Ok(())
                 } else {
                     debug!("{} is wrong, updating", linkfile.display());
-                    match std::fs::remove_file(&linkfile) {
-                        Err(err) => {
-                            bail!(
-                                "Failed to delete {}, cannot update alias: {}",
-                                linkfile.display(),
-                                err.to_string()
-                            );
-                        },
-                        _ => {}
+                    if let Err(err) = std::fs::remove_file(linkfile) {
+                        bail!(
+                            "Failed to delete {}, cannot update alias: {}",
+                            linkfile.display(),
+                            err.to_string()
+                        );
                     }
                 }
             }
@@ -106,14 +106,13 @@ pub fn add_alias(ver: &str, alias: &str) -> Result<(), Box<dyn Error>> {
 
     // If we are still here, then we need to create the link
     debug!("Adding {} -> {}", linkfile.display(), target.display());
-    match symlink(&target, &linkfile) {
-        Err(err) => bail!(
+    if let Err(err) = symlink(target, linkfile) {
+        bail!(
             "Cannot create alias {}: {}",
             linkfile.display(),
             err.to_string()
-        ),
-        _ => {}
-    };
+        );
+    }
 
     Ok(())
 }
@@ -128,23 +127,25 @@ pub fn add_alias(ver: &str, alias: &str) -> Result<(), Box<dyn Error>> {
     // should exist at this point, but make sure
     std::fs::create_dir_all(&bin)?;
 
-    let filename = "R-".to_string() + alias + ".bat";
-    let linkfile = bin.join(&filename);
+    for tool in get_quick_links()? {
+        let filename = tool.to_owned() + "-" + alias + ".bat";
+        let linkfile = bin.join(&filename);
 
-    let cnt = "@\"C:\\Program Files\\R\\R-".to_string() + &ver + "\\bin\\R\" %*\n";
-    let op;
-    if linkfile.exists() {
-        op = "Updating";
-        let orig = std::fs::read_to_string(&linkfile)?;
-        if orig == cnt {
-            return Ok(());
-        }
-    } else {
-        op = "Adding";
-    };
-    info!("{} R-{} -> {} alias", op, alias, ver);
-    let mut file = File::create(&linkfile)?;
-    file.write_all(cnt.as_bytes())?;
+        let cnt = quick_link_bat(ver, &tool);
+        let op;
+        if linkfile.exists() {
+            op = "Updating";
+            let orig = std::fs::read_to_string(&linkfile)?;
+            if orig == cnt {
+                continue;
+            }
+        } else {
+            op = "Adding";
+        };
+        info!("{} {}-{} -> {} alias", op, tool, alias, ver);
+        let mut file = File::create(&linkfile)?;
+        file.write_all(cnt.as_bytes())?;
+    }
 
     Ok(())
 }
@@ -156,30 +157,39 @@ pub fn add_alias(ver: &str, alias: &str) -> Result<(), Box<dyn Error>> {
 
     info!("Adding R-{} alias to R {}", alias, ver);
 
-    let base = Path::new(R_ROOT);
-    let target = base.join(ver).join("bin/R");
-    let linkfile = Path::new("/usr/local/bin/").join("R-".to_string() + alias);
+    let bin = get_r_version_root(ver)?.join(ver).join("bin");
+    for tool in get_quick_links()? {
+        let target = bin.join(&tool);
+        if !target.exists() {
+            debug!("No {} in R {}, no {}-{} alias", tool, ver, tool, alias);
+            continue;
+        }
+        let linkfile = get_quick_link_dir()?.join(tool + "-" + alias);
+        add_alias_link(&target, &linkfile)?;
+    }
+
+    Ok(())
+}
 
+#[cfg(target_os = "linux")]
+fn add_alias_link(target: &Path, linkfile: &Path) -> Result<(), Box<dyn Error>> {
     // If it exists then we check that it points to the right place
     // Cannot use .exists(), because it follows symlinks
-    let meta = std::fs::symlink_metadata(&linkfile);
+    let meta = std::fs::symlink_metadata(linkfile);
     if meta.is_ok() {
-        match std::fs::read_link(&linkfile) {
+        match std::fs::read_link(linkfile) {
             Err(_) => bail!("{} is not a symlink, aborting", linkfile.display()),
             Ok(xtarget) => {
                 if xtarget == target {
                     return Ok(())
                 } else {
                     debug!("{} is wrong, updating", linkfile.display());
-                    match std::fs::remove_file(&linkfile) {
-                        Err(err) => {
-                            bail!(
-                                "Failed to delete {}, cannot update alias: {}",
-                                linkfile.display(),
-                                err.to_string()
-                            );
-                        },
-                        _ => {}
+                    if let Err(err) = std::fs::remove_file(linkfile) {
+                        bail!(
+                            "Failed to delete {}, cannot update alias: {}",
+                            linkfile.display(),
+                            err.to_string()
+                        );
                     }
                 }
             }
@@ -188,14 +198,144 @@ pub fn add_alias(ver: &str, alias: &str) -> Result<(), Box<dyn Error>> {
 
     // If we are still here, then we need to create the link
     debug!("Adding {} -> {}", linkfile.display(), target.display());
-    match symlink(&target, &linkfile) {
-        Err(err) => bail!(
+    if let Err(err) = symlink(target, linkfile) {
+        bail!(
             "Cannot create alias {}: {}",
             linkfile.display(),
             err.to_string()
-        ),
-        _ => {}
-    };
+        );
+    }
+
+    Ok(())
+}
+
+// -- user defined aliases ------------------------------------------------
+
+// These are set by `rig add`, and found from the quick links
+const BUILTIN_ALIASES: [&str; 4] = ["release", "oldrel", "devel", "next"];
+
+// Aliases from the quick links, plus the user defined aliases from the
+// config. Aliases of R versions that are not installed are dropped later.
+
+pub fn find_all_aliases() -> Result<Vec<Alias>, Box<dyn Error>> {
+    let config = get_config_aliases()?;
+    let mut result: Vec<Alias> = find_aliases()?
+        .into_iter()
+        .filter(|x| !config.contains_key(&x.alias))
+        .collect();
+    for (alias, version) in config {
+        result.push(Alias { alias, version });
+    }
+    result.sort_by(|a, b| a.alias.cmp(&b.alias));
+    Ok(result)
+}
+
+// Add the alias links, and also save user defined aliases in the config
+pub fn set_alias(ver: &str, alias: &str) -> Result<(), Box<dyn Error>> {
+    add_alias(ver, alias)?;
+    if !BUILTIN_ALIASES.contains(&alias) {
+        set_config_alias(alias, Some(ver))?;
+    }
+    Ok(())
+}
+
+pub fn remove_alias(alias: &str) -> Result<(), Box<dyn Error>> {
+    let msg = "Removing R-".to_string() + alias + " alias";
+    escalate(&msg)?;
+    info!("Removing R-{} alias", alias);
+
+    for tool in get_quick_links()? {
+        let linkfile = get_alias_link(&tool, alias)?;
+        if std::fs::symlink_metadata(&linkfile).is_ok() {
+            debug!("Removing {}", linkfile.display());
+            std::fs::remove_file(&linkfile)?;
+        }
+    }
+    set_config_alias(alias, None)
+}
+
+// `rig rm` removes the user defined aliases of the removed R version
+pub fn remove_version_aliases(ver: &str) -> Result<(), Box<dyn Error>> {
+    for (alias, aver) in get_config_aliases()? {
+        if aver == ver {
+            remove_alias(&alias)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub fn get_alias_link(tool: &str, alias: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_quick_link_dir()?.join(tool.to_string() + "-" + alias))
+}
+
+#[cfg(target_os = "windows")]
+pub fn get_alias_link(tool: &str, alias: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(Path::new(R_ROOT)
+        .join("bin")
+        .join(tool.to_string() + "-" + alias + ".bat"))
+}
+
+pub fn sc_alias(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        Some(("add", s)) => sc_alias_add(s),
+        Some(("list", s)) => sc_alias_list(s, args, mainargs),
+        Some(("rm", s)) => sc_alias_rm(s),
+        _ => Ok(()), // unreachable
+    }
+}
+
+fn sc_alias_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let alias = require_with!(args.value_of("alias"), "clap error");
+    let version = require_with!(args.value_of("version"), "clap error");
+
+    let re = Regex::new("^[A-Za-z][A-Za-z0-9_.-]*$")?;
+    if !re.is_match(alias) {
+        rig_bail!(
+            InvalidSpec,
+            "Invalid alias: {}, it must start with a letter, and contain only \
+             letters, numbers, `_`, `.` and `-`",
+            alias
+        );
+    }
+    if BUILTIN_ALIASES.contains(&alias) {
+        bail!("The {} alias is managed by `rig add`", alias);
+    }
+    if sc_get_list()?.iter().any(|x| x == alias) {
+        bail!("There is an R version called {}, cannot use it as an alias", alias);
+    }
+
+    let ver = check_installed(&version.to_string())?;
+    set_alias(&ver, alias)
+}
+
+fn sc_alias_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let alias = require_with!(args.value_of("alias"), "clap error");
+    if !find_all_aliases()?.iter().any(|x| x.alias == alias) {
+        bail!("No such alias: {}, see `rig alias list`", alias);
+    }
+    remove_alias(alias)
+}
+
+fn sc_alias_list(
+    args: &ArgMatches,
+    aliasargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let aliases = find_all_aliases()?;
+
+    let format = get_output_format(&[args, aliasargs, mainargs]);
+    if format != OutputFormat::Text {
+        print_output(format, &aliases)?;
+    } else {
+        let mut tab = Table::new("{:<}  {:<}");
+        tab.add_row(row!["alias", "version"]);
+        tab.add_heading("------------------------------------------");
+        for alias in aliases {
+            tab.add_row(row!(alias.alias, alias.version));
+        }
+        print!("{}", tab);
+    }
 
     Ok(())
 }
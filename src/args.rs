@@ -4,7 +4,7 @@
 
 // crates used here need to go in build-dependencies as well !!!
 
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgGroup, ArgMatches, Command};
 
 #[cfg(target_os = "macos")]
 use simplelog::*;
@@ -20,36 +20,46 @@ std::include!("help-windows.in");
 #[cfg(target_os = "linux")]
 std::include!("help-linux.in");
 
-pub fn rig_app() -> Command<'static> {
+// The default of `--arch`, arm64 if the machine can run it
+#[cfg(target_os = "macos")]
+pub fn default_arch() -> &'static str {
     let _arch_x86_64: &'static str = "x86_64";
     let _arch_arm64: &'static str = "arm64";
     let mut _default_arch: &'static str = "";
 
-    #[cfg(target_os = "macos")]
-    {
-        let proc = std::process::Command::new("arch")
-            .args(["-arm64", "true"])
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .spawn();
-
-        if let Ok(mut proc) = proc {
-            let out = proc.wait();
-            if let Ok(out) = out {
-                if out.success() {
-                    _default_arch = _arch_arm64;
-                } else {
-                    _default_arch = _arch_x86_64;
-                }
+    let proc = std::process::Command::new("arch")
+        .args(["-arm64", "true"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+
+    if let Ok(mut proc) = proc {
+        let out = proc.wait();
+        if let Ok(out) = out {
+            if out.success() {
+                _default_arch = _arch_arm64;
+            } else {
+                _default_arch = _arch_x86_64;
             }
-        } else {
-            _default_arch = _arch_x86_64;
         }
+    } else {
+        _default_arch = _arch_x86_64;
+    }
+
+    if _default_arch == "" {
+        warn!("Failed to detect arch, default is 'x86_64'.");
+        _default_arch = _arch_x86_64;
+    };
+
+    _default_arch
+}
 
-        if _default_arch == "" {
-            warn!("Failed to detect arch, default is 'x86_64'.");
-            _default_arch = _arch_x86_64;
-        };
+pub fn rig_app() -> Command<'static> {
+    let mut _default_arch: &'static str = "";
+
+    #[cfg(target_os = "macos")]
+    {
+        _default_arch = default_arch();
     }
 
     let mut rig = Command::new("RIG -- The R Installation Manager")
@@ -95,9 +105,9 @@ pub fn rig_app() -> Command<'static> {
     cmd_add = cmd_add
         .arg(
             Arg::new("str")
-                .help("R version to install")
+                .help("R version(s) to install")
                 .default_value("release")
-                .multiple_occurrences(false),
+                .multiple_values(true),
         )
         .arg(
             Arg::new("without-cran-mirror")
@@ -118,6 +128,26 @@ pub fn rig_app() -> Command<'static> {
                 .required(false)
                 .possible_values(["stable", "rc", "devel"])
                 .default_value("stable"),
+        )
+        .arg(
+            Arg::new("from-project")
+                .help("Install the R version pinned by the current project")
+                .long("from-project")
+                .conflicts_with("str")
+                .required(false),
+        )
+        .arg(
+            Arg::new("sha256")
+                .help("Expected SHA-256 digest of the installer")
+                .long("sha256")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("no-verify")
+                .help("Do not require a checksum, even if `verify-download` is set")
+                .long("no-verify")
+                .required(false),
         );
 
     #[cfg(any(target_os = "windows", target_os = "linux"))]
@@ -137,6 +167,12 @@ pub fn rig_app() -> Command<'static> {
                 .help("Do not set up system requirements installation.")
                 .long("without-sysreqs")
                 .required(false),
+        )
+        .arg(
+            Arg::new("from-source")
+                .help("Build R from source, e.g. on unsupported distros.")
+                .long("from-source")
+                .required(false),
         );
     }
 
@@ -248,6 +284,20 @@ pub fn rig_app() -> Command<'static> {
 	cmd_system = cmd_system.subcommand(cmd_system_update_rtools40);
     }
 
+    #[cfg(target_os = "linux")]
+    {
+        let cmd_system_distros = Command::new("distros")
+            .about("List supported Linux distributions")
+            .long_about(HELP_SYSTEM_DISTROS)
+            .arg(
+                Arg::new("json")
+                    .help("JSON output")
+                    .long("json")
+                    .required(false),
+            );
+        cmd_system = cmd_system.subcommand(cmd_system_distros);
+    }
+
     #[cfg(target_os = "macos")]
     {
         let cmd_system_ortho = Command::new("make-orthogonal")
@@ -330,10 +380,82 @@ pub fn rig_app() -> Command<'static> {
             .subcommand(cmd_system_allow_core_dumps);
     }
 
+    let cmd_system_repos = Command::new("repos")
+        .about("Show or change the package repositories of an R version")
+        .long_about(HELP_SYSTEM_REPOS)
+        .after_help(HELP_SYSTEM_REPOS_EXAMPLES)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .required(false),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Show the repository settings")
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .required(false),
+                )
+                .arg(Arg::new("version").help("R version").required(true)),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("Change repository settings")
+                .group(
+                    ArgGroup::new("settings")
+                        .args(&["cran", "rspm", "sysreqs"])
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::new("cran")
+                        .help("CRAN mirror URL")
+                        .long("cran")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("rspm")
+                        .help("RSPM URL")
+                        .long("rspm")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("sysreqs")
+                        .help("Whether to install system requirements automatically")
+                        .long("sysreqs")
+                        .takes_value(true)
+                        .possible_values(["true", "false"]),
+                )
+                .arg(Arg::new("version").help("R version").required(true)),
+        )
+        .subcommand(
+            Command::new("unset")
+                .about("Remove repository settings")
+                .group(
+                    ArgGroup::new("settings")
+                        .args(&["cran", "rspm", "sysreqs"])
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(Arg::new("cran").help("Remove the CRAN mirror").long("cran"))
+                .arg(Arg::new("rspm").help("Remove RSPM").long("rspm"))
+                .arg(
+                    Arg::new("sysreqs")
+                        .help("Remove the system requirements setting")
+                        .long("sysreqs"),
+                )
+                .arg(Arg::new("version").help("R version").required(true)),
+        );
+
     cmd_system = cmd_system
         .subcommand(cmd_system_links)
         .subcommand(cmd_system_lib)
-        .subcommand(cmd_system_pak);
+        .subcommand(cmd_system_pak)
+        .subcommand(cmd_system_repos);
 
     let mut cmd_resolve = Command::new("resolve")
         .about("Resolve a symbolic R version")
@@ -382,6 +504,172 @@ pub fn rig_app() -> Command<'static> {
                 .required(false),
         );
 
+    let cmd_run = Command::new("run")
+        .about("Run R or Rscript with the specified R version")
+        .long_about(HELP_RUN)
+        .after_help(HELP_RUN_EXAMPLES)
+        .arg(
+            Arg::new("rscript")
+                .help("Run Rscript instead of R")
+                .long("rscript")
+                .required(false),
+        )
+        .arg(
+            Arg::new("version")
+                .help("R version, alias or renv.lock file, default is the pinned or default version")
+                .required(false),
+        )
+        .arg(
+            Arg::new("args")
+                .help("Arguments for R or Rscript, after `--`")
+                .required(false)
+                .multiple_values(true)
+                .allow_hyphen_values(true)
+                .last(true),
+        );
+
+    let cmd_shell_init = Command::new("shell-init")
+        .about("Print shell integration code, for `rig use`")
+        .long_about(HELP_SHELL_INIT)
+        .after_help(HELP_SHELL_INIT_EXAMPLES)
+        .arg(
+            Arg::new("shell")
+                .help("Shell")
+                .required(true)
+                .possible_values(["bash", "zsh", "fish"]),
+        )
+        .arg(
+            Arg::new("auto")
+                .help("Switch R version automatically, when entering a project")
+                .long("auto")
+                .required(false),
+        );
+
+    let cmd_sync = Command::new("sync")
+        .about("Install and set up R versions, as listed in rig.toml")
+        .long_about(HELP_SYNC)
+        .after_help(HELP_SYNC_EXAMPLES)
+        .arg(
+            Arg::new("dry-run")
+                .help("Only print what would be done")
+                .long("dry-run")
+                .required(false),
+        )
+        .arg(
+            Arg::new("prune")
+                .help("Also remove R versions that are not listed")
+                .long("prune")
+                .required(false),
+        )
+        .arg(
+            Arg::new("file")
+                .help("rig.toml file to use")
+                .long("file")
+                .takes_value(true)
+                .default_value("rig.toml"),
+        );
+
+    let cmd_upgrade = Command::new("upgrade")
+        .about("Upgrade R versions to the latest patch release")
+        .long_about(HELP_UPGRADE)
+        .after_help(HELP_UPGRADE_EXAMPLES)
+        .arg(
+            Arg::new("all")
+                .help("Upgrade all installed minor R versions")
+                .long("all")
+                .required(false),
+        )
+        .arg(
+            Arg::new("remove-old")
+                .help("Remove the old patch release after the upgrade")
+                .long("remove-old")
+                .required(false),
+        )
+        .arg(
+            Arg::new("minor")
+                .help("Minor R version(s) to upgrade, e.g. 4.1")
+                .multiple_values(true)
+                .required_unless_present("all")
+                .conflicts_with("all"),
+        );
+
+    let cmd_use = Command::new("use")
+        .about("Use an R version in the current shell")
+        .long_about(HELP_USE)
+        .arg(
+            Arg::new("version")
+                .help("R version, alias or renv.lock file")
+                .required_unless_present("auto"),
+        )
+        .arg(
+            Arg::new("shell")
+                .help("Shell, set by the shell integration")
+                .long("shell")
+                .takes_value(true)
+                .required(false)
+                .hide(true),
+        )
+        .arg(
+            Arg::new("auto")
+                .help("Use the version of the current project")
+                .long("auto")
+                .required(false)
+                .hide(true),
+        );
+
+    let cmd_which = Command::new("which")
+        .about("Print the path of the R binary for the current project")
+        .long_about(HELP_WHICH)
+        .arg(
+            Arg::new("version")
+                .help("R version, default is the pinned or default version")
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .required(false),
+        );
+
+    let cmd_alias = Command::new("alias")
+        .about("Manage R version aliases")
+        .long_about(HELP_ALIAS)
+        .after_help(HELP_ALIAS_EXAMPLES)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .required(false),
+        )
+        .subcommand(
+            Command::new("list")
+                .aliases(&["ls"])
+                .about("List aliases [alias: ls]")
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Add an alias, or change the R version of an alias")
+                .arg(Arg::new("alias").help("Name of the alias").required(true))
+                .arg(
+                    Arg::new("version")
+                        .help("R version or alias to point the alias to")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("rm")
+                .about("Remove an alias")
+                .arg(Arg::new("alias").help("Name of the alias").required(true)),
+        );
+
     let cmd_library = Command::new("library")
         .about("Manage package libraries [alias: lib] (experimental)")
         .long_about(HELP_LIBRARY)
@@ -438,6 +726,156 @@ pub fn rig_app() -> Command<'static> {
                 ),
         );
 
+    let cmd_cache = Command::new("cache")
+        .about("Manage the cache of downloaded R installers")
+        .long_about(HELP_CACHE)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("list")
+                .about("List cached installers")
+                .aliases(&["ls"])
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("Remove cached installers")
+                .arg(
+                    Arg::new("older-than")
+                        .help("Only remove installers older than this, e.g. 30d or 12h")
+                        .long("older-than")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            Command::new("prefetch")
+                .about("Download R installers into the cache")
+                .arg(
+                    Arg::new("platform")
+                        .help("Platform(s) to download installers for")
+                        .long("platform")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("version")
+                        .help("R versions to download, default is release")
+                        .required(false)
+                        .multiple_occurrences(true),
+                ),
+        );
+
+    let cmd_doctor = Command::new("doctor")
+        .about("Find and fix problems with R installations")
+        .long_about(HELP_DOCTOR)
+        .after_help(HELP_DOCTOR_EXAMPLES)
+        .arg(
+            Arg::new("fix")
+                .help("Fix the problems that can be fixed safely")
+                .long("fix")
+                .required(false),
+        )
+        .arg(
+            Arg::new("json")
+                .help("JSON output")
+                .long("json")
+                .required(false),
+        );
+
+    let cmd_config = Command::new("config")
+        .about("Manage rig settings")
+        .long_about(HELP_CONFIG)
+        .after_help(HELP_CONFIG_EXAMPLES)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("list")
+                .about("List all settings and their values [alias: ls]")
+                .aliases(&["ls"])
+                .arg(
+                    Arg::new("json")
+                        .help("JSON output")
+                        .long("json")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("get")
+                .about("Print the value of a setting")
+                .arg(Arg::new("key").help("name of the setting").required(true))
+                .arg(
+                    Arg::new("show-secret")
+                        .help("Print secrets, e.g. passwords, as well")
+                        .long("show-secret")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("Change a setting")
+                .arg(
+                    Arg::new("system")
+                        .help("Update the system configuration, for all users")
+                        .long("system")
+                        .required(false),
+                )
+                .arg(Arg::new("key").help("name of the setting").required(true))
+                .arg(
+                    Arg::new("value")
+                        .help("new value of the setting")
+                        .required(true)
+                        .allow_hyphen_values(true),
+                ),
+        )
+        .subcommand(
+            Command::new("unset")
+                .about("Remove a setting, to use the default value")
+                .arg(
+                    Arg::new("system")
+                        .help("Update the system configuration, for all users")
+                        .long("system")
+                        .required(false),
+                )
+                .arg(Arg::new("key").help("name of the setting").required(true)),
+        );
+
+    let cmd_mirror = Command::new("mirror")
+        .about("Manage a local mirror of R installers, for offline installs")
+        .long_about(HELP_MIRROR)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("sync")
+                .about("Download R installers into a mirror directory")
+                .long_about(HELP_MIRROR_SYNC)
+                .after_help(HELP_MIRROR_SYNC_EXAMPLES)
+                .arg(
+                    Arg::new("dir")
+                        .help("Mirror directory, default is the configured mirror")
+                        .long("dir")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("platform")
+                        .help("Platform(s) to download installers for")
+                        .long("platform")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("version")
+                        .help("R versions to download, default is release")
+                        .required(false)
+                        .multiple_occurrences(true),
+                ),
+        );
+
     #[cfg(target_os = "macos")]
     {
         let cmd_sysreqs = Command::new("sysreqs")
@@ -498,6 +936,24 @@ pub fn rig_app() -> Command<'static> {
         rig = rig.subcommand(cmd_sysreqs);
     }
 
+    #[cfg(target_os = "linux")]
+    {
+        rig = rig.arg(
+            Arg::new("distro-manifest")
+                .help("Distro manifest file to use instead of the built-in one")
+                .long("distro-manifest")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("user")
+                .help("Manage user installations, in the home directory, without sudo")
+                .long("user")
+                .required(false)
+                .global(true),
+        );
+    }
+
     rig = rig.arg(
         Arg::new("quiet")
             .help("Suppress output (overrides `--verbose`)")
@@ -519,14 +975,34 @@ pub fn rig_app() -> Command<'static> {
             .long("json")
             .required(false),
     )
+    .arg(
+        Arg::new("format")
+            .help("Output format, `--json` is the same as `--format json`")
+            .long("format")
+            .required(false)
+            .takes_value(true)
+            .possible_values(["json", "yaml", "tsv"])
+            .global(true),
+    )
     .subcommand(cmd_default)
+    .subcommand(cmd_alias)
     .subcommand(cmd_list)
     .subcommand(cmd_add)
+    .subcommand(cmd_cache)
+    .subcommand(cmd_config)
+    .subcommand(cmd_doctor)
     .subcommand(cmd_rm)
     .subcommand(cmd_system)
     .subcommand(cmd_resolve)
     .subcommand(cmd_rstudio)
+    .subcommand(cmd_run)
+    .subcommand(cmd_shell_init)
+    .subcommand(cmd_sync)
+    .subcommand(cmd_upgrade)
+    .subcommand(cmd_use)
     .subcommand(cmd_library)
+    .subcommand(cmd_mirror)
+    .subcommand(cmd_which)
     .after_help(HELP_EXAMPLES);
 
     rig
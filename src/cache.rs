@@ -0,0 +1,376 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use simple_error::*;
+use simplelog::*;
+use tabular::*;
+
+use crate::config::*;
+use crate::download::*;
+use crate::mirror::*;
+use crate::output::*;
+use crate::resolve::*;
+use crate::utils::*;
+
+// Downloaded installers are kept in the cache directory, in the rig data
+// directory. The index has an entry for each file, keyed by the URL.
+
+const CACHE_INDEX_VERSION: u32 = 1;
+
+// Installers may be downloaded in parallel, e.g. for `rig add` with
+// multiple versions, and each download updates the index.
+static CACHE_INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+// Snapshot builds (devel, next) are only reused for a day (or
+// `cache-max-age`), other installers are reused until they are removed
+// from the cache.
+const SNAPSHOT_MAX_AGE: Duration = Duration::from_secs(60 * 60 * 24);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub url: String,
+    pub file: String,
+    pub version: Option<String>,
+    pub arch: Option<String>,
+    pub size: u64,
+    pub sha256: String,
+    // Unix time, in seconds
+    pub fetched: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheIndex {
+    version: u32,
+    entries: Vec<CacheEntry>,
+}
+
+pub fn cache_dir() -> Result<PathBuf, Box<dyn Error>> {
+    match get_config_value("cache-dir")? {
+        Some(x) => Ok(PathBuf::from(x)),
+        None => Ok(rig_config_dir()?.join("cache")),
+    }
+}
+
+fn cache_index_file() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(cache_dir()?.join("index.json"))
+}
+
+fn now_secs() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(x) => x.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+impl CacheEntry {
+    fn age(&self) -> Duration {
+        Duration::from_secs(now_secs().saturating_sub(self.fetched))
+    }
+
+    fn path(&self) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(cache_dir()?.join(&self.file))
+    }
+}
+
+impl CacheIndex {
+    fn load() -> Result<CacheIndex, Box<dyn Error>> {
+        let path = cache_index_file()?;
+        if !path.exists() {
+            return Ok(CacheIndex {
+                version: CACHE_INDEX_VERSION,
+                entries: vec![],
+            });
+        }
+        let contents = read_file_string(&path)?;
+        let index: CacheIndex = match serde_json::from_str(&contents) {
+            Ok(x) => x,
+            Err(err) => bail!(
+                "Invalid cache index at {}: {}, run `rig cache clean`",
+                path.display(),
+                err.to_string()
+            ),
+        };
+        Ok(index)
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = cache_index_file()?;
+        std::fs::create_dir_all(cache_dir()?)?;
+        let str = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, str)?;
+        Ok(())
+    }
+
+    fn find(&self, url: &str) -> Option<&CacheEntry> {
+        self.entries.iter().find(|x| x.url == url)
+    }
+
+    fn insert(&mut self, entry: CacheEntry) {
+        self.entries.retain(|x| x.url != entry.url);
+        self.entries.push(entry);
+    }
+}
+
+pub fn cache_max_age(spec: &str) -> Option<Duration> {
+    if spec == "devel" || spec == "next" || is_remote_url(spec) {
+        let age = get_config_value("cache-max-age").ok().flatten();
+        match age.map(|x| parse_age(&x)) {
+            Some(Ok(x)) => Some(x),
+            Some(Err(err)) => {
+                warn!("{}", err);
+                Some(SNAPSHOT_MAX_AGE)
+            }
+            None => Some(SNAPSHOT_MAX_AGE),
+        }
+    } else {
+        None
+    }
+}
+
+// Download `url` into the cache, or use the cached file, if it is there
+// and it is not older than `max_age`. See `verify_download()` for
+// `sha256` and `required`.
+
+pub fn cache_download(
+    url: &str,
+    filename: &str,
+    version: Option<String>,
+    arch: Option<String>,
+    max_age: Option<Duration>,
+    sha256: Option<&str>,
+    required: bool,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let index = CacheIndex::load()?;
+
+    if let Some(entry) = index.find(url) {
+        let path = entry.path()?;
+        let fresh = match max_age {
+            Some(max_age) => entry.age() < max_age,
+            None => true,
+        };
+        if path.exists() && fresh {
+            info!("{} is cached at {}", filename, path.display());
+            // Checks the file against the index, unless a digest was given
+            verify_download(url, &path, Some(sha256.unwrap_or(&entry.sha256)), required)?;
+            return Ok(path);
+        }
+    }
+
+    let hash = calculate_hash(url);
+    let file = hash[..16].to_string() + "-" + filename;
+    let path = cache_dir()?.join(&file);
+    info!("Downloading {} -> {}", url, path.display());
+    let client = &new_client()?;
+    download_file(client, url, path.as_os_str())?;
+    let sha256 = verify_download(url, &path, sha256, required)?;
+
+    let size = std::fs::metadata(&path)?.len();
+    let arch = arch.map(|x| {
+        if x == "default" {
+            std::env::consts::ARCH.to_string()
+        } else {
+            x
+        }
+    });
+
+    // Other downloads might have updated the index in the meanwhile
+    let _lock = CACHE_INDEX_LOCK.lock();
+    let mut index = CacheIndex::load()?;
+    index.insert(CacheEntry {
+        url: url.to_string(),
+        file,
+        version,
+        arch,
+        size,
+        sha256,
+        fetched: now_secs(),
+    });
+    index.save()?;
+
+    Ok(path)
+}
+
+// ------------------------------------------------------------------------
+
+pub fn sc_cache(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        Some(("list", s)) => sc_cache_list(s, mainargs),
+        Some(("clean", s)) => sc_cache_clean(s),
+        Some(("prefetch", s)) => sc_cache_prefetch(s),
+        _ => Ok(()), // unreachable
+    }
+}
+
+#[derive(Serialize)]
+struct CacheListEntry {
+    #[serde(flatten)]
+    entry: CacheEntry,
+    #[serde(serialize_with = "crate::output::serialize_path")]
+    path: PathBuf,
+}
+
+fn sc_cache_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let index = CacheIndex::load()?;
+
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        let mut out: Vec<CacheListEntry> = vec![];
+        for entry in &index.entries {
+            out.push(CacheListEntry {
+                path: entry.path()?,
+                entry: entry.clone(),
+            });
+        }
+        print_output(format, &out)?;
+    } else {
+        let mut tab = Table::new("{:<}  {:<}  {:>}  {:>}  {:<}");
+        tab.add_row(row!["version", "arch", "size", "age", "url"]);
+        tab.add_heading("------------------------------------------");
+        for entry in &index.entries {
+            tab.add_row(row!(
+                entry.version.as_deref().unwrap_or(""),
+                entry.arch.as_deref().unwrap_or(""),
+                format!("{:.1} MB", entry.size as f64 / 1024.0 / 1024.0),
+                format_age(entry.age()),
+                &entry.url
+            ));
+        }
+        print!("{}", tab);
+    }
+
+    Ok(())
+}
+
+fn sc_cache_clean(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let older_than = match args.value_of("older-than") {
+        Some(x) => Some(parse_age(x)?),
+        None => None,
+    };
+
+    let mut index = CacheIndex::load()?;
+    let num = index.entries.len();
+    let mut keep: Vec<CacheEntry> = vec![];
+    let mut freed: u64 = 0;
+    for entry in index.entries {
+        let remove = match older_than {
+            Some(older_than) => entry.age() > older_than,
+            None => true,
+        };
+        if !remove {
+            keep.push(entry);
+            continue;
+        }
+        let path = entry.path()?;
+        if path.exists() {
+            debug!("Removing {}", path.display());
+            if let Err(err) = std::fs::remove_file(&path) {
+                bail!("Cannot remove {}: {}", path.display(), err.to_string());
+            }
+            freed += entry.size;
+        }
+    }
+
+    let removed = num - keep.len();
+    index.entries = keep;
+    index.save()?;
+
+    // Without --older-than also clean up leftovers, e.g. failed downloads
+    if older_than.is_none() {
+        remove_unindexed(&cache_dir()?, &index)?;
+    }
+
+    info!(
+        "Removed {} cached file{}, {:.1} MB",
+        removed,
+        if removed == 1 { "" } else { "s" },
+        freed as f64 / 1024.0 / 1024.0
+    );
+
+    Ok(())
+}
+
+fn remove_unindexed(dir: &Path, index: &CacheIndex) -> Result<(), Box<dyn Error>> {
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        let name = file.file_name().to_string_lossy().to_string();
+        if name == "index.json" || index.entries.iter().any(|x| x.file == name) {
+            continue;
+        }
+        let path = file.path();
+        debug!("Removing {}", path.display());
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn sc_cache_prefetch(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let vers: Vec<String> = match args.values_of("version") {
+        Some(x) => x.map(|v| v.to_string()).collect(),
+        None => vec!["release".to_string()],
+    };
+    let platforms: Vec<String> = match args.values_of("platform") {
+        Some(x) => x.map(|v| v.to_string()).collect(),
+        None => vec![default_platform()?],
+    };
+
+    for platform in platforms {
+        let (os, arch, linux) = parse_platform(&platform)?;
+        let rvers = resolve_versions(vers.to_owned(), os, arch, linux)?;
+        for (spec, rver) in vers.iter().zip(rvers.iter()) {
+            let version = rver.version.to_owned().unwrap_or("???".to_string());
+            let url = match &rver.url {
+                Some(x) => x,
+                None => {
+                    warn!("No installer for R {} on {}, skipping", version, platform);
+                    continue;
+                }
+            };
+            let filename = basename(url).unwrap_or("foo");
+            cache_download(
+                url,
+                filename,
+                rver.version.to_owned(),
+                rver.arch.to_owned(),
+                cache_max_age(spec),
+                rver.sha256.as_deref(),
+                true,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// `30d`, `12h`, `45m`, or a number of days
+fn parse_age(x: &str) -> Result<Duration, Box<dyn Error>> {
+    let (num, unit) = match x.chars().last() {
+        Some('d') => (&x[..x.len() - 1], 60 * 60 * 24),
+        Some('h') => (&x[..x.len() - 1], 60 * 60),
+        Some('m') => (&x[..x.len() - 1], 60),
+        _ => (x, 60 * 60 * 24),
+    };
+    match num.parse::<u64>() {
+        Ok(num) => Ok(Duration::from_secs(num * unit)),
+        Err(_) => bail!("Invalid age: {}, use e.g. `30d` or `12h`", x),
+    }
+}
+
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    if secs >= 60 * 60 * 24 {
+        format!("{}d", secs / 60 / 60 / 24)
+    } else if secs >= 60 * 60 {
+        format!("{}h", secs / 60 / 60)
+    } else {
+        format!("{}m", secs / 60)
+    }
+}
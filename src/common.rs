@@ -5,7 +5,7 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use clap::ArgMatches;
-use simple_error::bail;
+use simple_error::*;
 use simplelog::*;
 
 #[cfg(target_os = "macos")]
@@ -17,7 +17,12 @@ use crate::windows::*;
 #[cfg(target_os = "linux")]
 use crate::linux::*;
 
+use crate::alias::*;
+use crate::cache::*;
+use crate::config::*;
+use crate::error::*;
 use crate::escalate::escalate;
+use crate::project::*;
 use crate::renv;
 use crate::rversion::*;
 use crate::run::*;
@@ -36,7 +41,7 @@ pub fn check_installed(x: &String) -> Result<String, Box<dyn Error>> {
         }
     }
 
-    bail!("R version <b>{}</b> is not installed", &x);
+    rig_bail!(NotInstalled, "R version <b>{}</b> is not installed", &x);
 }
 
 // -- rig default ---------------------------------------------------------
@@ -63,12 +68,13 @@ pub fn set_default_if_none(ver: String) -> Result<(), Box<dyn Error>> {
 
 pub fn sc_get_list_details() -> Result<Vec<InstalledVersion>, Box<dyn Error>> {
     let names = sc_get_list()?;
-    let aliases = find_aliases()?;
+    let aliases = find_all_aliases()?;
     let mut res: Vec<InstalledVersion> = vec![];
     let re = Regex::new("^Version:[ ]?")?;
 
     for name in names {
-        let desc = Path::new(R_ROOT)
+        let root = get_r_version_root(&name)?;
+        let desc = root
             .join(R_SYSLIBPATH.replace("{}", &name))
             .join("base/DESCRIPTION");
         let lines = match read_lines(&desc) {
@@ -81,8 +87,8 @@ pub fn sc_get_list_details() -> Result<Vec<InstalledVersion>, Box<dyn Error>> {
         } else {
             Some(re.replace(&lines[idx[0]], "").to_string())
         };
-        let path = Path::new(R_ROOT).join(R_VERSIONDIR.replace("{}", &name));
-        let binary = Path::new(R_ROOT).join(R_BINPATH.replace("{}", &name));
+        let path = root.join(R_VERSIONDIR.replace("{}", &name));
+        let binary = root.join(R_BINPATH.replace("{}", &name));
         let mut myaliases: Vec<String> = vec![];
         for a in &aliases {
             if a.version == name {
@@ -94,13 +100,155 @@ pub fn sc_get_list_details() -> Result<Vec<InstalledVersion>, Box<dyn Error>> {
             version: version,
             path: path.to_str().and_then(|x| Some(x.to_string())),
             binary: binary.to_str().and_then(|x| Some(x.to_string())),
-            aliases: myaliases
+            aliases: myaliases,
+            root: root.to_str().map(|x| x.to_string()),
         });
     }
 
     Ok(res)
 }
 
+// -- rig add options -----------------------------------------------------
+
+// The options of `rig add` for a single R version. `rig sync`, `rig
+// upgrade` and `rig add` with multiple versions install R with these,
+// without parsing a command line again. If `version` is set, then the
+// version is already resolved, and if `installer` is set, too, then the
+// installer is already downloaded.
+
+#[derive(Clone, Debug, Default)]
+pub struct AddOptions {
+    pub spec: String,
+    pub sha256: Option<String>,
+    // `cran-mirror`, `pak`, etc. from the `--without-*` options
+    pub without: Vec<String>,
+    pub pak_version: Option<String>,
+    #[cfg(target_os = "linux")]
+    pub from_source: bool,
+    #[cfg(target_os = "macos")]
+    pub arch: String,
+    #[cfg(target_os = "windows")]
+    pub without_translations: bool,
+    #[cfg(target_os = "windows")]
+    pub with_desktop_icon: bool,
+    pub version: Option<Rversion>,
+    pub installer: Option<PathBuf>,
+}
+
+#[cfg(target_os = "macos")]
+const ADD_WITHOUT: [&str; 2] = ["cran-mirror", "pak"];
+#[cfg(target_os = "windows")]
+const ADD_WITHOUT: [&str; 3] = ["cran-mirror", "pak", "rspm"];
+#[cfg(target_os = "linux")]
+const ADD_WITHOUT: [&str; 4] = ["cran-mirror", "pak", "rspm", "sysreqs"];
+
+impl AddOptions {
+    // The defaults, i.e. `rig add <spec>`
+    pub fn new(spec: &str) -> AddOptions {
+        AddOptions {
+            spec: spec.to_string(),
+            #[cfg(target_os = "macos")]
+            arch: crate::args::default_arch().to_string(),
+            ..Default::default()
+        }
+    }
+
+    // The options of the `rig add` command line, for `spec`
+    pub fn from_args(args: &ArgMatches, spec: &str) -> AddOptions {
+        let mut opts = AddOptions::new(spec);
+        opts.sha256 = args.value_of("sha256").map(|x| x.to_string());
+        opts.without = ADD_WITHOUT
+            .iter()
+            .filter(|x| args.is_present(&("without-".to_string() + x)))
+            .map(|x| x.to_string())
+            .collect();
+        if args.occurrences_of("pak-version") > 0 {
+            opts.pak_version = args.value_of("pak-version").map(|x| x.to_string());
+        }
+        #[cfg(target_os = "linux")]
+        {
+            opts.from_source = args.is_present("from-source");
+        }
+        #[cfg(target_os = "macos")]
+        if let Some(arch) = args.value_of("arch") {
+            opts.arch = arch.to_string();
+        }
+        #[cfg(target_os = "windows")]
+        {
+            opts.without_translations = args.is_present("without-translations");
+            opts.with_desktop_icon = args.is_present("with-desktop-icon");
+        }
+        opts
+    }
+
+    // `--without-<what>`, or `add-<what>` is false in the config
+    pub fn without(&self, what: &str) -> Result<bool, Box<dyn Error>> {
+        if self.without.iter().any(|x| x == what) {
+            return Ok(true);
+        }
+        Ok(!get_config_bool(&("add-".to_string() + what))?)
+    }
+
+    // `--pak-version` if specified, otherwise `pak-version` from the config
+    pub fn pak_version(&self) -> Result<String, Box<dyn Error>> {
+        if let Some(pakver) = &self.pak_version {
+            return Ok(pakver.to_string());
+        }
+        Ok(get_config_value("pak-version")?.unwrap_or("stable".to_string()))
+    }
+
+    // Resolve versions to install with these options
+    #[cfg(target_os = "macos")]
+    pub fn resolve_specs(&self, specs: Vec<String>) -> Result<Vec<Rversion>, Box<dyn Error>> {
+        get_resolve_specs(specs, &self.arch)
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn resolve_specs(&self, specs: Vec<String>) -> Result<Vec<Rversion>, Box<dyn Error>> {
+        get_resolve_specs(specs)
+    }
+
+    // Resolve the version and download its installer, or find it in the
+    // cache, unless this was done already
+    pub fn download(&self) -> Result<(Rversion, PathBuf), Box<dyn Error>> {
+        if let (Some(version), Some(installer)) = (&self.version, &self.installer) {
+            return Ok((version.to_owned(), installer.to_owned()));
+        }
+        let version = match &self.version {
+            Some(x) => x.to_owned(),
+            None => self.resolve_specs(vec![self.spec.to_string()])?[0].to_owned(),
+        };
+        let url: String = match &version.url {
+            Some(s) => s.to_string(),
+            None => rig_bail!(
+                InvalidSpec,
+                "Cannot find a download url for R version {}",
+                version.version.as_deref().unwrap_or("???")
+            ),
+        };
+        let filename = basename(&url).unwrap_or("foo");
+        let target = cache_download(
+            &url,
+            filename,
+            version.version.to_owned(),
+            version.arch.to_owned(),
+            cache_max_age(&self.spec),
+            self.sha256.as_deref().or(version.sha256.as_deref()),
+            true,
+        )?;
+        Ok((version, target))
+    }
+}
+
+// `--pak-version` if specified, otherwise `pak-version` from the config
+pub fn get_pak_version(args: &ArgMatches) -> Result<String, Box<dyn Error>> {
+    if args.occurrences_of("pak-version") > 0 {
+        let pakver = require_with!(args.value_of("pak-version"), "clap error");
+        return Ok(pakver.to_string());
+    }
+    Ok(get_config_value("pak-version")?.unwrap_or("stable".to_string()))
+}
+
 // -- rig system add-pak (implementation) ---------------------------------
 
 pub fn system_add_pak(
@@ -206,7 +354,70 @@ pub fn sc_rstudio(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         }
     }
 
+    // Use the version pinned by the project, if there is a pin
+    if ver.is_none() {
+        if let Some(pin) = find_project_pin()? {
+            let usever = match_pin(&pin)?;
+            info!("Using R {}, pinned by {}", usever.name, pin.file.display());
+            return sc_rstudio_(Some(&usever.name), prj, None);
+        }
+    }
+
     sc_rstudio_(ver, prj, None)
 }
 
+// -- rig run -------------------------------------------------------------
+
+pub fn sc_run(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let ver = match args.value_of("version") {
+        Some(x) if x.ends_with("renv.lock") => {
+            let needver = renv::parse_r_version(PathBuf::new().join(x))?;
+            renv::match_r_version(&needver)?.name
+        }
+        Some(x) => match_version_installed(x)?,
+        None => match find_project_pin()? {
+            Some(pin) => match_pin(&pin)?.name,
+            None => sc_get_default_or_fail()?,
+        },
+    };
+
+    let bin = if args.is_present("rscript") {
+        get_rscript_binary(&ver)?
+    } else {
+        get_r_binary(&ver)?
+    };
+    if !bin.exists() {
+        bail!("Cannot find {} for R {}", bin.display(), ver);
+    }
+
+    let rargs: Vec<&str> = match args.values_of("args") {
+        Some(x) => x.collect(),
+        None => vec![],
+    };
+    debug!("Running {} with args {:?}", bin.display(), rargs);
+
+    let mut cmd = std::process::Command::new(&bin);
+    cmd.args(&rargs);
+    exec_command(cmd, &bin)
+}
+
+// Replace rig with the R process, so signals and the exit status go
+// straight to the caller.
+
+#[cfg(unix)]
+fn exec_command(mut cmd: std::process::Command, bin: &Path) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::process::CommandExt;
+    let err = cmd.exec();
+    rig_bail!(ExternalCommand, "Failed to run {}: {}", bin.display(), err);
+}
+
+#[cfg(windows)]
+fn exec_command(mut cmd: std::process::Command, bin: &Path) -> Result<(), Box<dyn Error>> {
+    let status = match cmd.status() {
+        Ok(x) => x,
+        Err(err) => rig_bail!(ExternalCommand, "Failed to run {}: {}", bin.display(), err),
+    };
+    std::process::exit(status.code().unwrap_or(1));
+}
+
 // ------------------------------------------------------------------------
@@ -1,59 +1,343 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use directories::ProjectDirs;
-use simple_error::{bail, SimpleError};
+use clap::ArgMatches;
+use directories::{BaseDirs, ProjectDirs};
+use serde_json::Value;
+use simple_error::*;
+use tabular::*;
 
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+use crate::escalate::*;
+use crate::output::*;
 use crate::utils::*;
 
-#[derive(Serialize, Deserialize, Debug)]
+// The rig settings live in `config.json` in the rig data directory. A
+// system-wide `config.json` in `/etc/rig` (`%ProgramData%\rig` on
+// Windows) is layered under it. Environment variables take precedence
+// over both files.
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 struct Config {
-    #[serde(default = "empty_stringmap")]
+    #[serde(default = "empty_stringmap", skip_serializing_if = "HashMap::is_empty")]
     userlibrary: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    aliases: BTreeMap<String, String>,
+    #[serde(flatten)]
+    settings: BTreeMap<String, Value>,
+}
+
+#[cfg(target_os = "windows")]
+const PATH_LIST_SEPARATOR: &str = ";";
+#[cfg(not(target_os = "windows"))]
+const PATH_LIST_SEPARATOR: &str = ":";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfigType {
+    Str,
+    Bool,
+    Int,
+    // A list, with this separator in the environment variable
+    List(&'static str),
+    Choice(&'static [&'static str]),
 }
 
+struct ConfigKey {
+    key: &'static str,
+    typ: ConfigType,
+    env: &'static str,
+    default: Option<&'static str>,
+    help: &'static str,
+}
+
+const CONFIG_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        key: "add-cran-mirror",
+        typ: ConfigType::Bool,
+        env: "RIG_ADD_CRAN_MIRROR",
+        default: Some("true"),
+        help: "`rig add` sets the CRAN mirror",
+    },
+    ConfigKey {
+        key: "add-pak",
+        typ: ConfigType::Bool,
+        env: "RIG_ADD_PAK",
+        default: Some("true"),
+        help: "`rig add` installs pak",
+    },
+    ConfigKey {
+        key: "add-rspm",
+        typ: ConfigType::Bool,
+        env: "RIG_ADD_RSPM",
+        default: Some("true"),
+        help: "`rig add` sets up RSPM",
+    },
+    ConfigKey {
+        key: "add-sysreqs",
+        typ: ConfigType::Bool,
+        env: "RIG_ADD_SYSREQS",
+        default: Some("true"),
+        help: "`rig add` sets up system requirements installation",
+    },
+    ConfigKey {
+        key: "api-url",
+        typ: ConfigType::Str,
+        env: "RIG_API_URL",
+        default: Some("https://api.r-hub.io/rversions"),
+        help: "R versions API URL",
+    },
+    ConfigKey {
+        key: "ca-bundle",
+        typ: ConfigType::Str,
+        env: "RIG_CA_BUNDLE",
+        default: None,
+        help: "PEM file with extra CA certificates",
+    },
+    ConfigKey {
+        key: "cache-dir",
+        typ: ConfigType::Str,
+        env: "RIG_CACHE_DIR",
+        default: None,
+        help: "Download cache directory",
+    },
+    ConfigKey {
+        key: "cache-max-age",
+        typ: ConfigType::Str,
+        env: "RIG_CACHE_MAX_AGE",
+        default: Some("1d"),
+        help: "Max age of cached devel and next builds",
+    },
+    ConfigKey {
+        key: "cdn-url",
+        typ: ConfigType::Str,
+        env: "RIG_CDN_URL",
+        default: Some("https://cdn.rstudio.com"),
+        help: "Posit CDN URL",
+    },
+    ConfigKey {
+        key: "client-cert",
+        typ: ConfigType::Str,
+        env: "RIG_CLIENT_CERT",
+        default: None,
+        help: "PKCS#12 client certificate file",
+    },
+    ConfigKey {
+        key: "client-cert-password",
+        typ: ConfigType::Str,
+        env: "RIG_CLIENT_CERT_PASSWORD",
+        default: None,
+        help: "Password of the client certificate",
+    },
+    ConfigKey {
+        key: "cran-archive-url",
+        typ: ConfigType::Str,
+        env: "RIG_CRAN_ARCHIVE_URL",
+        default: Some("https://cran-archive.r-project.org"),
+        help: "CRAN archive URL, for old Windows installers",
+    },
+    ConfigKey {
+        key: "cran-mirror",
+        typ: ConfigType::Str,
+        env: "RIG_CRAN_MIRROR",
+        default: Some("https://cloud.r-project.org"),
+        help: "CRAN mirror that `rig add` sets",
+    },
+    ConfigKey {
+        key: "cran-url",
+        typ: ConfigType::Str,
+        env: "RIG_CRAN_URL",
+        default: Some("https://cloud.r-project.org"),
+        help: "CRAN URL for downloading R",
+    },
+    ConfigKey {
+        key: "download-retries",
+        typ: ConfigType::Int,
+        env: "RIG_DOWNLOAD_RETRIES",
+        default: Some("3"),
+        help: "Number of download retries",
+    },
+    ConfigKey {
+        key: "download-timeout",
+        typ: ConfigType::Int,
+        env: "RIG_DOWNLOAD_TIMEOUT",
+        default: Some("60"),
+        help: "Seconds to wait for a stalled download",
+    },
+    ConfigKey {
+        key: "http-proxy",
+        typ: ConfigType::Str,
+        env: "HTTP_PROXY",
+        default: None,
+        help: "Proxy for HTTP",
+    },
+    ConfigKey {
+        key: "https-proxy",
+        typ: ConfigType::Str,
+        env: "HTTPS_PROXY",
+        default: None,
+        help: "Proxy for HTTPS",
+    },
+    ConfigKey {
+        key: "mac-url",
+        typ: ConfigType::Str,
+        env: "RIG_MAC_URL",
+        default: Some("https://mac.r-project.org"),
+        help: "URL of the macOS R-devel builds",
+    },
+    ConfigKey {
+        key: "mirror",
+        typ: ConfigType::Str,
+        env: "RIG_MIRROR",
+        default: None,
+        help: "Local mirror directory or URL",
+    },
+    ConfigKey {
+        key: "no-proxy",
+        typ: ConfigType::Str,
+        env: "NO_PROXY",
+        default: None,
+        help: "Hosts to access without a proxy",
+    },
+    ConfigKey {
+        key: "pak-version",
+        typ: ConfigType::Choice(&["stable", "rc", "devel"]),
+        env: "RIG_PAK_VERSION",
+        default: Some("stable"),
+        help: "pak version to install",
+    },
+    ConfigKey {
+        key: "quick-links",
+        typ: ConfigType::List(","),
+        env: "RIG_QUICK_LINKS",
+        default: Some("R,Rscript"),
+        help: "Tools that get quick links",
+    },
+    ConfigKey {
+        key: "r-root",
+        typ: ConfigType::List(PATH_LIST_SEPARATOR),
+        env: "RIG_R_ROOT",
+        default: None,
+        help: "R installation directories (Linux)",
+    },
+    ConfigKey {
+        key: "svn-url",
+        typ: ConfigType::Str,
+        env: "RIG_SVN_URL",
+        default: Some("https://svn.r-project.org"),
+        help: "R source repository URL, for the R-devel version",
+    },
+    ConfigKey {
+        key: "user",
+        typ: ConfigType::Bool,
+        env: "RIG_USER",
+        default: Some("false"),
+        help: "User installs, without sudo (Linux)",
+    },
+    ConfigKey {
+        key: "verify-download",
+        typ: ConfigType::Bool,
+        env: "RIG_VERIFY_DOWNLOAD",
+        default: Some("false"),
+        help: "`rig add` requires a SHA-256 checksum for installers",
+    },
+];
+
 fn empty_stringmap() -> HashMap<String, String> {
     HashMap::<String, String>::new()
 }
 
-fn rig_config_file() -> Result<PathBuf, Box<dyn Error>> {
+pub fn rig_config_dir() -> Result<PathBuf, Box<dyn Error>> {
     let proj_dirs = match ProjectDirs::from("com", "gaborcsardi", "rig") {
         Some(x) => x,
         None => bail!("Config file is not supported on this system"),
     };
-    let config_dir = proj_dirs.data_dir();
-    let config_file = config_dir.join("config.json");
+    Ok(proj_dirs.data_dir().to_path_buf())
+}
+
+fn rig_config_file() -> Result<PathBuf, Box<dyn Error>> {
+    let config_file = rig_config_dir()?.join("config.json");
+
+    // After `sudo` this is still the config of the user that called rig
+    if let (Some(home), Some(base)) = (invoking_user_home(), BaseDirs::new()) {
+        if let Ok(rel) = config_file.strip_prefix(base.home_dir()) {
+            return Ok(Path::new(&home).join(rel));
+        }
+    }
+
     Ok(config_file)
 }
 
+// `escalate()` sets `RIG_HOME`, but rig might have been started with
+// `sudo` as well
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn invoking_user_home() -> Option<String> {
+    std::env::var("RIG_HOME").ok().or_else(|| get_home().ok())
+}
+
+#[cfg(target_os = "windows")]
+fn invoking_user_home() -> Option<String> {
+    None
+}
+
+#[cfg(not(target_os = "windows"))]
+fn rig_system_config_file() -> PathBuf {
+    PathBuf::from("/etc/rig/config.json")
+}
+
+#[cfg(target_os = "windows")]
+fn rig_system_config_file() -> PathBuf {
+    let pd = std::env::var("ProgramData").unwrap_or("C:\\ProgramData".to_string());
+    PathBuf::from(pd).join("rig").join("config.json")
+}
+
 impl Config {
-    fn load() -> Result<Config, Box<dyn Error>> {
-        let config_file = rig_config_file()?;
+    fn load_file(config_file: &Path) -> Result<Config, Box<dyn Error>> {
         let config: Config = if config_file.exists() {
-            let contents = read_file_string(&config_file)?;
-            serde_json::from_str(&contents)?
+            let contents = read_file_string(config_file)?;
+            match serde_json::from_str(&contents) {
+                Ok(x) => x,
+                Err(err) => bail!("Invalid config file {}: {}", config_file.display(), err),
+            }
         } else {
-            serde_json::from_str::<Config>("{}")?
+            Config::default()
         };
 
         Ok(config)
     }
 
-    fn save(&self) -> Result<(), Box<dyn Error>> {
+    fn load() -> Result<Config, Box<dyn Error>> {
+        Config::load_file(&rig_config_file()?)
+    }
+
+    fn load_system() -> Result<Config, Box<dyn Error>> {
+        Config::load_file(&rig_system_config_file())
+    }
+
+    fn save_file(&self, config_file: &Path) -> Result<(), Box<dyn Error>> {
         let str = serde_json::to_string_pretty(self)?;
-        let config_file = rig_config_file()?;
         let parent = config_file
             .parent()
             .ok_or(SimpleError::new("Invalid config file directory"))?;
-        std::fs::create_dir_all(&parent)?;
-        std::fs::write(config_file, str)?;
+        if config_file == rig_system_config_file() {
+            std::fs::create_dir_all(&parent)?;
+            std::fs::write(config_file, str)?;
+        } else {
+            create_user_dir_all(parent)?;
+            std::fs::write(config_file, str)?;
+            chown_to_user(config_file)?;
+        }
         Ok(())
     }
 
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        self.save_file(&rig_config_file()?)
+    }
+
     fn get_userlibrary(&self, rver: &str) -> Option<String> {
         self.userlibrary.get(rver).and_then(|x| Some(x.to_string()))
     }
@@ -79,6 +363,40 @@ pub fn save_config(rver: &str, key: &str, value: Option<&str>) -> Result<(), Box
     Ok(())
 }
 
+// User defined aliases are system-wide, like the links that implement
+// them, so they are in the system config file, except for user installs
+// on Linux.
+
+fn rig_alias_config_file() -> Result<PathBuf, Box<dyn Error>> {
+    #[cfg(target_os = "linux")]
+    if crate::linux::is_user_mode() {
+        return rig_config_file();
+    }
+    Ok(rig_system_config_file())
+}
+
+pub fn get_config_aliases() -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    Ok(Config::load_file(&rig_alias_config_file()?)?.aliases)
+}
+
+pub fn set_config_alias(alias: &str, rver: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let config_file = rig_alias_config_file()?;
+    let mut config = Config::load_file(&config_file)?;
+    match rver {
+        None => config.aliases.remove(alias),
+        Some(x) => config.aliases.insert(alias.to_string(), x.to_string()),
+    };
+    config.save_file(&config_file)
+}
+
+// R versions with a cached user library path
+pub fn get_userlibrary_versions() -> Result<Vec<String>, Box<dyn Error>> {
+    let config = Config::load()?;
+    let mut vers: Vec<String> = config.userlibrary.keys().cloned().collect();
+    vers.sort();
+    Ok(vers)
+}
+
 pub fn get_config(rver: &str, key: &str) -> Result<Option<String>, Box<dyn Error>> {
     let config = Config::load()?;
     match key {
@@ -86,3 +404,305 @@ pub fn get_config(rver: &str, key: &str) -> Result<Option<String>, Box<dyn Error
         _ => bail!("Unknown config key: {}, internal error", key),
     }
 }
+
+// -- typed settings ------------------------------------------------------
+
+fn find_key(key: &str) -> Result<&'static ConfigKey, Box<dyn Error>> {
+    match CONFIG_KEYS.iter().find(|x| x.key == key) {
+        Some(x) => Ok(x),
+        None => bail!("Unknown config key: {}, see `rig config list`", key),
+    }
+}
+
+fn value_to_string(ck: &ConfigKey, value: &Value) -> String {
+    match (ck.typ, value) {
+        (_, Value::String(x)) => x.to_string(),
+        (ConfigType::List(sep), Value::Array(x)) => x
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => s.to_string(),
+                _ => v.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(sep),
+        _ => value.to_string(),
+    }
+}
+
+fn parse_value(ck: &ConfigKey, value: &str) -> Result<Value, Box<dyn Error>> {
+    match ck.typ {
+        ConfigType::Str => Ok(Value::String(value.to_string())),
+        ConfigType::Bool => match parse_bool(value) {
+            Some(x) => Ok(Value::Bool(x)),
+            None => bail!("Invalid value for {}: {}, must be true or false", ck.key, value),
+        },
+        ConfigType::Int => match value.parse::<u32>() {
+            Ok(x) => Ok(Value::from(x)),
+            Err(_) => bail!("Invalid value for {}: {}, must be a number", ck.key, value),
+        },
+        ConfigType::List(sep) => Ok(Value::Array(
+            value
+                .split(sep)
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(|x| Value::String(x.to_string()))
+                .collect(),
+        )),
+        ConfigType::Choice(choices) => {
+            if choices.contains(&value) {
+                Ok(Value::String(value.to_string()))
+            } else {
+                bail!(
+                    "Invalid value for {}: {}, must be one of {}",
+                    ck.key,
+                    value,
+                    choices.join(", ")
+                );
+            }
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// The value from the user or the system config file, and its source
+fn get_config_file_value(key: &str) -> Result<Option<(String, &'static str)>, Box<dyn Error>> {
+    let ck = find_key(key)?;
+    if let Some(value) = Config::load()?.settings.get(key) {
+        return Ok(Some((value_to_string(ck, value), "user")));
+    }
+    if let Some(value) = Config::load_system()?.settings.get(key) {
+        return Ok(Some((value_to_string(ck, value), "system")));
+    }
+    Ok(None)
+}
+
+// The value from the environment, the config files, or the default
+fn get_config_value_source(key: &str) -> Result<Option<(String, &'static str)>, Box<dyn Error>> {
+    let ck = find_key(key)?;
+    if let Ok(value) = std::env::var(ck.env) {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some((value, exported_source(key))));
+    }
+    if let Some(x) = get_config_file_value(key)? {
+        return Ok(Some(x));
+    }
+    Ok(ck.default.map(|x| (x.to_string(), "default")))
+}
+
+// Without the default value
+pub fn get_global_config_env(key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match get_config_value_source(key)? {
+        Some((_, "default")) => Ok(None),
+        x => Ok(x.map(|x| x.0)),
+    }
+}
+
+// Like `get_config_value()`, but it falls back to the default, also if
+// the setting is cleared with an empty environment variable
+pub fn get_config_string(key: &str) -> Result<String, Box<dyn Error>> {
+    if let Some(x) = get_config_value(key)? {
+        return Ok(x);
+    }
+    match find_key(key)?.default {
+        Some(x) => Ok(x.to_string()),
+        None => bail!("Config key {} is not set", key),
+    }
+}
+
+pub fn get_config_value(key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(get_config_value_source(key)?.map(|x| x.0))
+}
+
+pub fn get_config_bool(key: &str) -> Result<bool, Box<dyn Error>> {
+    let value = get_config_value(key)?.unwrap_or("false".to_string());
+    match parse_bool(&value) {
+        Some(x) => Ok(x),
+        None => bail!("Invalid value for {}: {}, must be true or false", key, value),
+    }
+}
+
+// Make sure that these settings are still used after escalating with
+// sudo, where we might not see the user's config file. We also record
+// where the exported values came from, for `rig config list`. Secrets
+// are not exported, they would end up in the environment of every
+// child process, the escalated rig reads them from the config file.
+
+const CONFIG_SOURCES_ENV: &str = "RIG_CONFIG_SOURCES";
+
+const SECRET_KEYS: [&str; 1] = ["client-cert-password"];
+
+fn mask_secret(key: &str, value: Option<String>) -> Option<String> {
+    if SECRET_KEYS.contains(&key) {
+        value.map(|_| "********".to_string())
+    } else {
+        value
+    }
+}
+
+pub fn export_config_env() {
+    let mut sources: Vec<String> = match std::env::var(CONFIG_SOURCES_ENV) {
+        Ok(x) => x.split(',').map(|x| x.to_string()).collect(),
+        Err(_) => vec![],
+    };
+    for ck in CONFIG_KEYS {
+        if SECRET_KEYS.contains(&ck.key) {
+            continue;
+        }
+        if std::env::var(ck.env).is_err() {
+            if let Ok(Some((value, source))) = get_config_file_value(ck.key) {
+                std::env::set_var(ck.env, value);
+                sources.push(ck.key.to_string() + "=" + source);
+            }
+        }
+    }
+    std::env::set_var(CONFIG_SOURCES_ENV, sources.join(","));
+}
+
+fn exported_source(key: &str) -> &'static str {
+    let sources = std::env::var(CONFIG_SOURCES_ENV).unwrap_or_default();
+    for src in sources.split(',') {
+        match src.split_once('=') {
+            Some((k, "user")) if k == key => return "user",
+            Some((k, "system")) if k == key => return "system",
+            _ => {}
+        }
+    }
+    "env"
+}
+
+// Tools that get quick links, e.g. `R-4.1.3` and `Rscript-4.1.3`.
+// `R` links are always created, aliases rely on them.
+
+pub fn get_quick_links() -> Result<Vec<String>, Box<dyn Error>> {
+    let mut tools = vec!["R".to_string()];
+    let conf = get_config_value("quick-links")?.unwrap_or("".to_string());
+    for tool in conf.split(',').map(|x| x.trim()) {
+        if !tool.is_empty() && !tools.iter().any(|x| x == tool) {
+            tools.push(tool.to_string());
+        }
+    }
+    Ok(tools)
+}
+
+// R installation roots from `r-root` or `RIG_R_ROOT`, a list of paths,
+// separated by `:` (`;` on Windows). New versions go into the first one.
+
+pub fn get_r_root_config() -> Result<Option<Vec<PathBuf>>, Box<dyn Error>> {
+    let conf = match get_global_config_env("r-root")? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let roots: Vec<PathBuf> = std::env::split_paths(&conf)
+        .filter(|x| !x.as_os_str().is_empty())
+        .collect();
+    if roots.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(roots))
+    }
+}
+
+// -- rig config ----------------------------------------------------------
+
+pub fn sc_config(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        Some(("list", s)) => sc_config_list(s, mainargs),
+        Some(("get", s)) => sc_config_get(s),
+        Some(("set", s)) => sc_config_set(s),
+        Some(("unset", s)) => sc_config_unset(s),
+        _ => Ok(()), // unreachable
+    }
+}
+
+#[derive(Serialize)]
+struct ConfigListEntry {
+    key: &'static str,
+    value: Option<String>,
+    source: &'static str,
+    description: &'static str,
+}
+
+fn sc_config_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut rows: Vec<(&ConfigKey, Option<String>, &str)> = vec![];
+    for ck in CONFIG_KEYS {
+        let (value, source) = match get_config_value_source(ck.key)? {
+            Some((v, s)) => (Some(v), s),
+            None => (None, ""),
+        };
+        // Do not print secrets
+        let value = mask_secret(ck.key, value);
+        rows.push((ck, value, source));
+    }
+
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        let out: Vec<ConfigListEntry> = rows
+            .into_iter()
+            .map(|(ck, value, source)| ConfigListEntry {
+                key: ck.key,
+                value,
+                source,
+                description: ck.help,
+            })
+            .collect();
+        print_output(format, &out)?;
+    } else {
+        let mut tab = Table::new("{:<}  {:<}  {:<}");
+        tab.add_row(row!["key", "value", "source"]);
+        tab.add_heading("------------------------------------------");
+        for (ck, value, source) in rows {
+            tab.add_row(row!(ck.key, value.unwrap_or("".to_string()), source));
+        }
+        print!("{}", tab);
+    }
+
+    Ok(())
+}
+
+fn sc_config_get(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let key = require_with!(args.value_of("key"), "clap error");
+    let value = get_config_value(key)?;
+    let value = if args.is_present("show-secret") { value } else { mask_secret(key, value) };
+    if let Some(value) = value {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+fn sc_config_set(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let key = require_with!(args.value_of("key"), "clap error");
+    let value = require_with!(args.value_of("value"), "clap error");
+    let ck = find_key(key)?;
+    let value = parse_value(ck, value)?;
+    update_config_file(args.is_present("system"), key, Some(value))
+}
+
+fn sc_config_unset(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let key = require_with!(args.value_of("key"), "clap error");
+    find_key(key)?;
+    update_config_file(args.is_present("system"), key, None)
+}
+
+fn update_config_file(system: bool, key: &str, value: Option<Value>) -> Result<(), Box<dyn Error>> {
+    let path = if system {
+        escalate("updating the system rig configuration")?;
+        rig_system_config_file()
+    } else {
+        rig_config_file()?
+    };
+    let mut config = Config::load_file(&path)?;
+    match value {
+        Some(value) => config.settings.insert(key.to_string(), value),
+        None => config.settings.remove(key),
+    };
+    config.save_file(&path)
+}
@@ -0,0 +1,166 @@
+#![cfg(target_os = "linux")]
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use simple_error::*;
+use simplelog::debug;
+
+use crate::config::*;
+use crate::rversion::*;
+use crate::utils::*;
+
+// The distro manifest that is embedded in rig. It can be overridden
+// with a `distros.json` file in the rig config directory, or with the
+// `--distro-manifest` option, or the `RIG_DISTRO_MANIFEST` env var.
+
+const DISTRO_MANIFEST: &str = include_str!("distros.json");
+const DISTRO_MANIFEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DistroManifest {
+    pub version: u32,
+    pub distros: Vec<DistroEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DistroEntry {
+    pub distro: String,
+    pub version: String,
+    // Other values of ID in /etc/os-release that use these builds
+    #[serde(default)]
+    pub ids: Vec<String>,
+    // UBUNTU_CODENAME in /etc/os-release, for Ubuntu derivatives
+    #[serde(default)]
+    pub codename: Option<String>,
+    // "exact" or "major"
+    #[serde(default = "default_version_match")]
+    pub version_match: String,
+    // "deb", "rpm" or "zypper"
+    pub installer: String,
+    // Download URL templates, per architecture
+    pub url: HashMap<String, String>,
+    #[serde(default)]
+    pub rspm_url: Option<String>,
+}
+
+fn default_version_match() -> String {
+    "exact".to_string()
+}
+
+impl DistroEntry {
+    fn matches(&self, id: &str, ver: &str) -> bool {
+        if self.distro != id && !self.ids.iter().any(|x| x == id) {
+            return false;
+        }
+        if self.version_match == "major" {
+            let major = ver.split('.').next().unwrap_or("");
+            self.version == major
+        } else {
+            self.version == ver
+        }
+    }
+
+    fn to_linux_version(&self) -> Option<LinuxVersion> {
+        let arch = std::env::consts::ARCH;
+        let url = self.url.get(arch)?;
+        let rspm_url = self.rspm_url.to_owned().unwrap_or_default();
+        Some(LinuxVersion {
+            distro: self.distro.to_owned(),
+            version: self.version.to_owned(),
+            url: url.to_owned(),
+            rspm: !rspm_url.is_empty(),
+            rspm_url,
+            installer: self.installer.to_owned(),
+        })
+    }
+}
+
+fn distro_manifest_path() -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if let Ok(path) = std::env::var("RIG_DISTRO_MANIFEST") {
+        if !path.is_empty() {
+            return Ok(Some(PathBuf::from(path)));
+        }
+    }
+
+    let path = rig_config_dir()?.join("distros.json");
+    if path.exists() {
+        Ok(Some(path))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn load_distro_manifest() -> Result<(DistroManifest, String), Box<dyn Error>> {
+    let (contents, source) = match distro_manifest_path()? {
+        None => (DISTRO_MANIFEST.to_string(), "built-in".to_string()),
+        Some(path) => {
+            let contents = match read_file_string(Path::new(&path)) {
+                Ok(x) => x,
+                Err(e) => bail!(
+                    "Cannot read distro manifest at {}: {}",
+                    path.display(),
+                    e.to_string()
+                ),
+            };
+            (contents, path.display().to_string())
+        }
+    };
+
+    debug!("Using distro manifest: {}", source);
+    let manifest: DistroManifest = try_with!(
+        serde_json::from_str(&contents),
+        "Invalid distro manifest at {}",
+        source
+    );
+
+    if manifest.version > DISTRO_MANIFEST_VERSION {
+        bail!(
+            "Distro manifest at {} has version {}, but this rig version \
+             only supports version {}, update rig",
+            source,
+            manifest.version,
+            DISTRO_MANIFEST_VERSION
+        );
+    }
+
+    Ok((manifest, source))
+}
+
+// Supported distros, for the current architecture
+
+pub fn list_supported_distros() -> Result<Vec<LinuxVersion>, Box<dyn Error>> {
+    let (manifest, _) = load_distro_manifest()?;
+    Ok(manifest
+        .distros
+        .iter()
+        .filter_map(|x| x.to_linux_version())
+        .collect())
+}
+
+pub fn match_distro(id: &str, ver: &str) -> Result<Option<LinuxVersion>, Box<dyn Error>> {
+    let (manifest, _) = load_distro_manifest()?;
+    for dis in &manifest.distros {
+        if dis.matches(id, ver) {
+            if let Some(lv) = dis.to_linux_version() {
+                return Ok(Some(lv));
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn match_distro_codename(codename: &str) -> Result<Option<LinuxVersion>, Box<dyn Error>> {
+    let (manifest, _) = load_distro_manifest()?;
+    for dis in &manifest.distros {
+        if dis.codename.as_deref() == Some(codename) {
+            if let Some(lv) = dis.to_linux_version() {
+                return Ok(Some(lv));
+            }
+        }
+    }
+    Ok(None)
+}
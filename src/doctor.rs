@@ -0,0 +1,383 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use regex::Regex;
+use serde_derive::Serialize;
+use simple_error::*;
+use simplelog::*;
+use tabular::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::alias::*;
+use crate::common::*;
+use crate::config::*;
+use crate::escalate::*;
+use crate::library::*;
+use crate::output::*;
+use crate::repos::*;
+use crate::utils::*;
+
+// `rig doctor` looks for problems with the R installations, and with the
+// files that rig manages: quick links, the default version, the system
+// profiles and the rig config. With `--fix` it repairs the problems that
+// it can repair without losing anything.
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+enum Fix {
+    AddAlias(String, String),
+    RemoveAlias(String),
+    RemoveLink(PathBuf),
+    LibraryMarkers(String, PathBuf),
+    RepoSettings(PathBuf),
+    UserLibrary(String),
+}
+
+#[derive(Serialize)]
+struct Finding {
+    severity: Severity,
+    check: &'static str,
+    message: String,
+    fixable: bool,
+    fixed: bool,
+    #[serde(skip)]
+    fix: Option<Fix>,
+}
+
+impl Finding {
+    fn new(severity: Severity, check: &'static str, message: String, fix: Option<Fix>) -> Finding {
+        Finding {
+            severity,
+            check,
+            message,
+            fixable: fix.is_some(),
+            fixed: false,
+            fix,
+        }
+    }
+}
+
+impl Fix {
+    // The cached user libraries are in the config of the user, the
+    // rest is system-wide
+    fn needs_root(&self) -> bool {
+        !matches!(self, Fix::UserLibrary(_))
+    }
+}
+
+pub fn sc_doctor(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let fix = args.is_present("fix");
+    let mut findings = find_problems()?;
+
+    if fix {
+        // This runs `rig doctor` again, as root, but that still uses the
+        // config of the user, see `rig_config_file()`
+        if findings.iter().filter_map(|x| x.fix.as_ref()).any(|f| f.needs_root()) {
+            escalate("fixing R installations")?;
+        }
+        for finding in findings.iter_mut() {
+            if let Some(f) = &finding.fix {
+                match apply_fix(f) {
+                    Ok(_) => finding.fixed = true,
+                    Err(err) => warn!("Failed to fix: {}: {}", finding.message, err),
+                };
+            }
+        }
+    }
+
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        print_output(format, &findings)?;
+    } else if findings.is_empty() {
+        info!("No problems found");
+    } else {
+        let mut tab = Table::new("{:<}  {:<}  {:<}  {:<}");
+        tab.add_row(row!["severity", "check", "fix", "problem"]);
+        tab.add_heading("------------------------------------------");
+        for finding in &findings {
+            let status = if finding.fixed {
+                "fixed"
+            } else if finding.fixable {
+                "--fix"
+            } else {
+                "manual"
+            };
+            tab.add_row(row!(finding.severity, finding.check, status, &finding.message));
+        }
+        print!("{}", tab);
+    }
+
+    let left = findings.iter().filter(|x| !x.fixed).count();
+    if left > 0 {
+        bail!(
+            "Found {} problem{}{}",
+            left,
+            if left == 1 { "" } else { "s" },
+            if !fix && findings.iter().any(|x| x.fixable) {
+                ", run `rig doctor --fix` to fix some of them"
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
+
+fn find_problems() -> Result<Vec<Finding>, Box<dyn Error>> {
+    let mut findings: Vec<Finding> = vec![];
+    check_installations(&mut findings)?;
+    #[cfg(unix)]
+    check_default(&mut findings)?;
+    #[cfg(unix)]
+    check_links(&mut findings)?;
+    check_aliases(&mut findings)?;
+    check_profiles(&mut findings)?;
+    check_config(&mut findings)?;
+    Ok(findings)
+}
+
+// A broken installation shows up as "(broken?)" in `rig list`
+fn check_installations(findings: &mut Vec<Finding>) -> Result<(), Box<dyn Error>> {
+    for ver in sc_get_list_details()? {
+        if ver.version.is_none() {
+            findings.push(Finding::new(
+                Severity::Error,
+                "install",
+                format!(
+                    "R {} is broken, cannot read its version from base/DESCRIPTION, \
+                     reinstall it or remove it with `rig rm`",
+                    ver.name
+                ),
+                None,
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn check_default(findings: &mut Vec<Finding>) -> Result<(), Box<dyn Error>> {
+    let current = get_r_current()?;
+    if std::fs::symlink_metadata(&current).is_ok() && !current.exists() {
+        findings.push(Finding::new(
+            Severity::Error,
+            "default",
+            format!(
+                "The default R version link {} points nowhere, set a default \
+                 with `rig default`",
+                current.display()
+            ),
+            Some(Fix::RemoveLink(current)),
+        ));
+        return Ok(());
+    }
+
+    if let Some(def) = sc_get_default()? {
+        if !sc_get_list()?.contains(&def) {
+            findings.push(Finding::new(
+                Severity::Error,
+                "default",
+                format!("The default R version, R {}, is not installed", def),
+                None,
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn check_links(findings: &mut Vec<Finding>) -> Result<(), Box<dyn Error>> {
+    let bindir = get_quick_link_dir()?;
+    if !bindir.exists() {
+        return Ok(());
+    }
+    let re = Regex::new("^(R|Rscript|[A-Za-z0-9_]+-[A-Za-z0-9_.-]+)$")?;
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&bindir)?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let fname = match path.file_name().and_then(|x| x.to_str()) {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+        if !re.is_match(&fname) {
+            continue;
+        }
+        // Only links that rig created, i.e. those pointing into R roots
+        if let Ok(target) = std::fs::read_link(&path) {
+            if !path.exists() && is_rig_link(&target)? {
+                findings.push(Finding::new(
+                    Severity::Warning,
+                    "links",
+                    format!("{} -> {} is a dangling link", path.display(), target.display()),
+                    Some(Fix::RemoveLink(path)),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_rig_link(target: &Path) -> Result<bool, Box<dyn Error>> {
+    let current = get_r_current()?;
+    let roots = get_r_roots()?;
+    Ok(target.starts_with(current) || roots.iter().any(|r| target.starts_with(r)))
+}
+
+// User defined aliases of removed R versions, or without links
+fn check_aliases(findings: &mut Vec<Finding>) -> Result<(), Box<dyn Error>> {
+    let vers = sc_get_list()?;
+    for (alias, ver) in get_config_aliases()? {
+        if !vers.contains(&ver) {
+            findings.push(Finding::new(
+                Severity::Warning,
+                "aliases",
+                format!("Alias {} is for R {}, which is not installed", alias, ver),
+                Some(Fix::RemoveAlias(alias)),
+            ));
+        } else if !get_alias_link("R", &alias)?.exists() {
+            findings.push(Finding::new(
+                Severity::Warning,
+                "aliases",
+                format!("The R-{} quick link of alias {} is missing", alias, alias),
+                Some(Fix::AddAlias(ver, alias)),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_profiles(findings: &mut Vec<Finding>) -> Result<(), Box<dyn Error>> {
+    let re_start = Regex::new("^## rig R_LIBS_USER start")?;
+    let re_end = Regex::new("^## rig R_LIBS_USER end")?;
+    for ver in sc_get_list()? {
+        let profile = get_system_profile(&ver)?;
+        if !profile.exists() {
+            continue;
+        }
+        let lines = match read_lines(&profile) {
+            Ok(x) => x,
+            Err(err) => {
+                findings.push(Finding::new(
+                    Severity::Error,
+                    "rprofile",
+                    format!("Cannot read {}: {}", profile.display(), err),
+                    None,
+                ));
+                continue;
+            }
+        };
+
+        let starts = grep_lines(&re_start, &lines);
+        let ends = grep_lines(&re_end, &lines);
+        let ok = (starts.is_empty() && ends.is_empty())
+            || (starts.len() == 1 && ends.len() == 1 && starts[0] < ends[0]);
+        if !ok {
+            findings.push(Finding::new(
+                Severity::Error,
+                "rprofile",
+                format!(
+                    "The user library setup of R {} in {} has {} start and {} end \
+                     markers, instead of a single pair",
+                    ver,
+                    profile.display(),
+                    starts.len(),
+                    ends.len()
+                ),
+                Some(Fix::LibraryMarkers(ver.to_string(), profile.to_owned())),
+            ));
+        }
+
+        let problems = check_repo_settings(&lines)?;
+        if !problems.is_empty() {
+            findings.push(Finding::new(
+                Severity::Warning,
+                "repos",
+                format!(
+                    "Unmanaged repository settings for R {} in {}: {}",
+                    ver,
+                    profile.display(),
+                    problems.join(", ")
+                ),
+                Some(Fix::RepoSettings(profile.to_owned())),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The library path of every R version is cached in the rig config, these
+// entries are stale once the R version is removed.
+fn check_config(findings: &mut Vec<Finding>) -> Result<(), Box<dyn Error>> {
+    let vers = sc_get_list()?;
+    for ver in get_userlibrary_versions()? {
+        if !vers.contains(&ver) {
+            findings.push(Finding::new(
+                Severity::Warning,
+                "config",
+                format!("Cached user library for R {}, which is not installed", ver),
+                Some(Fix::UserLibrary(ver)),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn apply_fix(fix: &Fix) -> Result<(), Box<dyn Error>> {
+    match fix {
+        Fix::AddAlias(ver, alias) => add_alias(ver, alias)?,
+        Fix::RemoveAlias(alias) => remove_alias(alias)?,
+        Fix::RemoveLink(path) => {
+            info!("Removing {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+        Fix::LibraryMarkers(ver, profile) => {
+            info!("Recreating user library setup of R {}", ver);
+            fix_library_markers(profile)?;
+            library_update_rprofile(ver)?;
+        }
+        Fix::RepoSettings(profile) => {
+            info!("Updating repository settings in {}", profile.display());
+            tidy_repo_settings(profile)?;
+        }
+        Fix::UserLibrary(ver) => {
+            info!("Removing cached user library of R {}", ver);
+            save_config(ver, "userlibrary", None)?;
+        }
+    };
+    Ok(())
+}
+
+// Remove all R_LIBS_USER blocks, and stray markers, then
+// `library_update_rprofile()` adds a new block.
+fn fix_library_markers(profile: &Path) -> Result<(), Box<dyn Error>> {
+    let lines = replace_profile_block(&read_lines(profile)?, "R_LIBS_USER", None)?;
+    write_lines(profile, &lines)
+}
@@ -2,81 +2,94 @@ use futures::future;
 use futures_util::StreamExt;
 use std::error::Error;
 use std::ffi::OsStr;
-#[cfg(any(target_os = "macos", target_os = "windows"))]
+#[cfg(target_os = "macos")]
 use std::ffi::OsString;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
-#[cfg(target_os = "windows")]
-use clap::ArgMatches;
-
-use simple_error::bail;
-#[cfg(any(target_os = "macos", target_os = "windows"))]
-use simplelog::info;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use simple_error::*;
+use simplelog::{debug, info, warn};
 
-#[cfg(target_os = "windows")]
-use crate::rversion::Rversion;
-#[cfg(any(target_os = "macos", target_os = "windows"))]
+#[cfg(target_os = "macos")]
+use crate::cache::*;
+use crate::config::*;
+use crate::error::*;
+use crate::mirror::*;
 use crate::utils::*;
-#[cfg(target_os = "windows")]
-use crate::windows::*;
 
-// ------------------------------------------------------------------------
-// synchronous API
-// ------------------------------------------------------------------------
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
 
-#[cfg(target_os = "windows")]
-pub fn download_r(args: &ArgMatches) -> Result<(Rversion, OsString), Box<dyn Error>> {
-    let version = get_resolve(args)?;
-    let version2 = version.to_owned();
-    let ver = version2.version;
-    let url: String = match &version.url {
-        Some(s) => s.to_string(),
-        None => bail!(
-            "Cannot find a download url for R version {}",
-            ver.unwrap_or("???".to_string())
-        ),
-    };
-    let mut filename = OsString::new();
-    filename.push(version2.arch.unwrap_or("".to_string()));
-    filename.push("-");
-    filename.push(basename(&url).unwrap_or("foo"));
-    let filename_path = Path::new(&filename);
-    let tmp_dir = std::env::temp_dir().join("rig");
-    let target = tmp_dir.join(&filename);
-    if target.exists() && not_too_old(&target) {
-        info!(
-            "{} is cached at {}",
-            filename_path.display(),
-            target.display()
-        );
-    } else {
-        info!("Downloading {} -> {}", url, target.display());
-        let client = &reqwest::Client::new();
-        download_file(client, &url, target.as_os_str())?;
+// All HTTP requests should use a client from here. reqwest uses the
+// proxies from the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` env vars
+// by default. We add the extra CA certificates and the client
+// certificate from the config.
+
+pub fn new_client() -> Result<reqwest::Client, Box<dyn Error>> {
+    let mut builder = reqwest::Client::builder().connect_timeout(get_download_timeout()?);
+
+    if let Some(bundle) = get_global_config_env("ca-bundle")? {
+        debug!("Using CA bundle at {}", bundle);
+        let pem = match std::fs::read(&bundle) {
+            Ok(x) => x,
+            Err(err) => bail!("Cannot read CA bundle {}: {}", bundle, err.to_string()),
+        };
+        for cert in split_pem_certificates(&pem) {
+            match reqwest::Certificate::from_pem(&cert) {
+                Ok(cert) => builder = builder.add_root_certificate(cert),
+                Err(err) => bail!("Invalid certificate in {}: {}", bundle, err.to_string()),
+            };
+        }
+    }
+
+    if let Some(cert) = get_global_config_env("client-cert")? {
+        debug!("Using client certificate at {}", cert);
+        let der = match std::fs::read(&cert) {
+            Ok(x) => x,
+            Err(err) => bail!("Cannot read client certificate {}: {}", cert, err.to_string()),
+        };
+        let password = get_global_config_env("client-cert-password")?.unwrap_or_default();
+        match reqwest::Identity::from_pkcs12_der(&der, &password) {
+            Ok(id) => builder = builder.identity(id),
+            Err(err) => bail!("Invalid client certificate {}: {}", cert, err.to_string()),
+        };
+    }
+
+    match builder.build() {
+        Ok(client) => Ok(client),
+        Err(err) => bail!("Cannot create HTTP client: {}", err.to_string()),
     }
+}
 
-    Ok((version, target.into_os_string()))
+// A CA bundle has multiple PEM certificates, but
+// `Certificate::from_pem()` only reads the first one.
+fn split_pem_certificates(pem: &[u8]) -> Vec<Vec<u8>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = String::from_utf8_lossy(pem);
+    let mut certs: Vec<Vec<u8>> = vec![];
+    let mut rest: &str = &pem;
+    while let Some(idx) = rest.find(END) {
+        let cert = &rest[..(idx + END.len())];
+        certs.push(cert.trim().as_bytes().to_vec());
+        rest = &rest[(idx + END.len())..];
+    }
+    certs
 }
 
+// ------------------------------------------------------------------------
+// synchronous API
+// ------------------------------------------------------------------------
+
 #[cfg(target_os = "macos")]
 pub fn download_file_sync(url: &str, filename: &str,
                           infinite_cache: bool)
                           -> Result<OsString, Box<dyn Error>> {
-    let tmp_dir = std::env::temp_dir().join("rig");
-    let target = tmp_dir.join(&filename);
-    if target.exists() && (infinite_cache || not_too_old(&target)) {
-        info!(
-            "{} is cached at {}",
-            filename,
-            target.display()
-        );
-    } else {
-        info!("Downloading {} -> {}", url, target.display());
-        let client = &reqwest::Client::new();
-        download_file(client, url, target.as_os_str())?;
-    }
+    let max_age = if infinite_cache { None } else { cache_max_age(url) };
+    let target = cache_download(url, filename, None, None, max_age, None, false)?;
 
     Ok(target.into_os_string())
 }
@@ -87,18 +100,13 @@ pub async fn download_file(
     url: &str,
     opath: &OsStr,
 ) -> Result<(), Box<dyn Error>> {
+    let url = &mirror_url(url)?;
+    if !is_remote_url(url) {
+        return copy_local_file(url, opath);
+    }
     let mut path = opath.to_os_string();
     path.push(".tmp");
     let path = Path::new(&path);
-    let resp = client.get(url).send().await;
-    let resp = match resp {
-        Ok(resp) => resp.error_for_status(),
-        Err(err) => bail!("HTTP error at {}: {}", url, err.to_string()),
-    };
-    let resp = match resp {
-        Ok(resp) => resp,
-        Err(err) => bail!("HTTP error at {}: {}", url, err.to_string()),
-    };
 
     // If dirname(path) is / then this is None
     let dir = Path::new(&path).parent();
@@ -114,38 +122,306 @@ pub async fn download_file(
         }
         None => {}
     };
-    let file = File::create(&path);
+
+    // If there is a partial download in `path` from an earlier attempt,
+    // then we try to resume it.
+    let retries = get_download_retries()?;
+    let timeout = get_download_timeout()?;
+    let mut attempt: u32 = 0;
+    loop {
+        match download_attempt(client, url, path, timeout).await {
+            Ok(_) => break,
+            Err(DownloadError::Fail(msg)) => bail!("{}", msg),
+            Err(DownloadError::Http(msg)) => rig_bail!(Network, "{}", msg),
+            Err(DownloadError::Retry(msg)) => {
+                if attempt >= retries {
+                    rig_bail!(Network, "{}", msg);
+                }
+                let wait = 1u64 << attempt;
+                attempt += 1;
+                warn!(
+                    "{}, retrying in {}s ({}/{})",
+                    msg, wait, attempt, retries
+                );
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+            }
+        }
+    }
+
+    match std::fs::rename(Path::new(&path), Path::new(&opath)) {
+        Err(err) => bail!("Failed to rename downloaded file: {}", err.to_string()),
+        _ => {}
+    };
+
+    Ok(())
+}
+
+// Failed download attempts are retried, unless retrying cannot help,
+// e.g. on a 404 (`Http`) or if we cannot write the output file (`Fail`).
+// A stalled connection is a failed attempt as well, if the server does
+// not send anything for `timeout`.
+enum DownloadError {
+    Retry(String),
+    Http(String),
+    Fail(String),
+}
+
+async fn download_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    timeout: Duration,
+) -> Result<(), DownloadError> {
+    let offset = match std::fs::metadata(path) {
+        Ok(md) => md.len(),
+        Err(_) => 0,
+    };
+    let mut req = client.get(url);
+    if offset > 0 {
+        debug!("Resuming download of {} at {} bytes", url, offset);
+        req = req.header(RANGE, format!("bytes={}-", offset));
+    }
+
+    let resp = match tokio::time::timeout(timeout, req.send()).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(err)) => {
+            return Err(DownloadError::Retry(format!(
+                "HTTP error at {}: {}",
+                url,
+                err
+            )))
+        }
+        Err(_) => return Err(stalled(url, timeout)),
+    };
+    let status = resp.status();
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        // Partial file is invalid, start over
+        let _ = std::fs::remove_file(path);
+        return Err(DownloadError::Retry(format!(
+            "Cannot resume download of {}",
+            url
+        )));
+    }
+    if status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+    {
+        return Err(DownloadError::Retry(format!("HTTP error at {}: {}", url, status)));
+    }
+    if !status.is_success() {
+        return Err(DownloadError::Http(format!("HTTP error at {}: {}", url, status)));
+    }
+
+    let resume = status == StatusCode::PARTIAL_CONTENT;
+    let file = if resume {
+        OpenOptions::new().append(true).open(path)
+    } else {
+        File::create(path)
+    };
     let mut file = match file {
         Ok(file) => file,
-        Err(err) => bail!(
-            "Cannot create file '{}': {}",
-            path.display(),
-            err.to_string()
-        ),
+        Err(err) => {
+            return Err(DownloadError::Fail(format!(
+                "Cannot create file '{}': {}",
+                path.display(),
+                err
+            )))
+        }
     };
+
+    let start = if resume { offset } else { 0 };
+    let total = resp.content_length().map(|x| x + start);
+    let mut progress = Progress::new(url, start, total);
     let mut stream = resp.bytes_stream();
 
-    while let Some(item) = stream.next().await {
+    loop {
+        let item = match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(Some(item)) => item,
+            Ok(None) => break,
+            Err(_) => return Err(stalled(url, timeout)),
+        };
         let chunk = match item {
             Ok(chunk) => chunk,
-            Err(err) => bail!("HTTP error at {}: {}", url, err.to_string()),
+            Err(err) => {
+                return Err(DownloadError::Retry(format!(
+                    "HTTP error at {}: {}",
+                    url,
+                    err
+                )))
+            }
         };
-        match file.write(&chunk) {
-            Err(err) => bail!(
+        if let Err(err) = file.write_all(&chunk) {
+            return Err(DownloadError::Fail(format!(
                 "Failed to write to file {}: {}",
                 path.display(),
-                err.to_string()
+                err
+            )));
+        }
+        progress.update(chunk.len() as u64);
+    }
+
+    Ok(())
+}
+
+fn stalled(url: &str, timeout: Duration) -> DownloadError {
+    DownloadError::Retry(format!(
+        "Download of {} stalled, no data in {}s",
+        url,
+        timeout.as_secs()
+    ))
+}
+
+fn get_download_timeout() -> Result<Duration, Box<dyn Error>> {
+    match get_global_config_env("download-timeout")? {
+        None => Ok(DEFAULT_DOWNLOAD_TIMEOUT),
+        Some(x) => match x.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Duration::from_secs(n)),
+            _ => bail!("Invalid download timeout: {}", x),
+        },
+    }
+}
+
+fn get_download_retries() -> Result<u32, Box<dyn Error>> {
+    match get_global_config_env("download-retries")? {
+        None => Ok(DEFAULT_DOWNLOAD_RETRIES),
+        Some(x) => match x.parse::<u32>() {
+            Ok(n) => Ok(n),
+            Err(_) => bail!("Invalid number of download retries: {}", x),
+        },
+    }
+}
+
+// Log a progress line every couple of seconds. These go to stderr, via
+// the logger, so `--quiet` turns them off.
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+
+struct Progress<'a> {
+    url: &'a str,
+    done: u64,
+    total: Option<u64>,
+    last: Instant,
+}
+
+impl<'a> Progress<'a> {
+    fn new(url: &'a str, done: u64, total: Option<u64>) -> Progress<'a> {
+        Progress {
+            url,
+            done,
+            total,
+            last: Instant::now(),
+        }
+    }
+
+    fn update(&mut self, bytes: u64) {
+        self.done += bytes;
+        if self.last.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last = Instant::now();
+        let name = basename(self.url).unwrap_or(self.url);
+        let mb = |x: u64| x as f64 / 1024.0 / 1024.0;
+        match self.total {
+            Some(total) if total > 0 => info!(
+                "{}: {}% ({:.1} of {:.1} MB)",
+                name,
+                self.done * 100 / total,
+                mb(self.done),
+                mb(total)
             ),
-            _ => {}
+            _ => info!("{}: {:.1} MB", name, mb(self.done)),
         };
     }
+}
+
+fn copy_local_file(from: &str, opath: &OsStr) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = Path::new(opath).parent() {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            bail!("Cannot create directory {}: {}", dir.display(), err.to_string());
+        }
+    }
+    if let Err(err) = std::fs::copy(from, opath) {
+        bail!("Cannot find {} in mirror: {}", from, err.to_string());
+    }
+    Ok(())
+}
 
-    match std::fs::rename(Path::new(&path), Path::new(&opath)) {
-        Err(err) => bail!("Failed to rename downloaded file: {}", err.to_string()),
-        _ => {}
+// Verify the SHA-256 checksum of a downloaded file. The digest is
+// either given explicitly (`--sha256` or the rversions API), or comes
+// from the `<url>.sha256` sidecar file. Without a digest we only warn,
+// unless `required` is set and the `verify-download` config key is true,
+// then the file is rejected. On failure we remove the file, so it is not
+// reused from the cache. Returns the checksum of the file.
+
+#[tokio::main]
+pub async fn verify_download(
+    url: &str,
+    target: &Path,
+    sha256: Option<&str>,
+    required: bool,
+) -> Result<String, Box<dyn Error>> {
+    let expected = match sha256 {
+        Some(x) => match parse_sha256(x) {
+            Some(x) => x,
+            None => rig_bail!(InvalidSpec, "Invalid SHA-256 digest: {}", x),
+        },
+        None => {
+            let client = new_client()?;
+            let sidecar = url.to_string() + ".sha256";
+            match download_text(&client, sidecar).await {
+                Ok(txt) => match parse_sha256(&txt) {
+                    Some(x) => x,
+                    None => {
+                        remove_download(target);
+                        bail!("Invalid checksum file at {}.sha256", url);
+                    }
+                },
+                Err(err) => {
+                    debug!("{}", err.to_string());
+                    if required && get_config_bool("verify-download")? {
+                        remove_download(target);
+                        bail!(
+                            "No SHA-256 checksum is available for {}, use `--sha256` to \
+                             specify it, or `--no-verify` to install it without one",
+                            url
+                        );
+                    }
+                    warn!("No checksum is available for {}, cannot verify it", url);
+                    return calculate_file_hash(target);
+                }
+            }
+        }
     };
 
-    Ok(())
+    let actual = calculate_file_hash(target)?;
+    if actual != expected {
+        remove_download(target);
+        bail!(
+            "SHA-256 checksum mismatch for {}, expected {}, got {}",
+            url,
+            expected,
+            actual
+        );
+    }
+    debug!("SHA-256 checksum of {} is OK: {}", target.display(), actual);
+
+    Ok(actual)
+}
+
+fn remove_download(target: &Path) {
+    if let Err(err) = std::fs::remove_file(target) {
+        warn!("Cannot remove {}: {}", target.display(), err.to_string());
+    }
+}
+
+pub fn parse_sha256(txt: &str) -> Option<String> {
+    // Either a single digest, or `<digest>  <filename>`
+    let digest = txt.split_whitespace().next()?.to_lowercase();
+    if digest.len() == 64 && digest.chars().all(|x| x.is_ascii_hexdigit()) {
+        Some(digest)
+    } else {
+        None
+    }
 }
 
 // ------------------------------------------------------------------------
@@ -156,19 +432,26 @@ pub async fn download_text(
     client: &reqwest::Client,
     url: String,
 ) -> Result<String, Box<dyn Error>> {
+    let url = mirror_url(&url)?;
+    if !is_remote_url(&url) {
+        return match std::fs::read_to_string(&url) {
+            Ok(txt) => Ok(txt),
+            Err(err) => bail!("Cannot find {} in mirror: {}", url, err.to_string()),
+        };
+    }
     let resp = client.get(&url).send().await;
     let body = match resp {
         Ok(resp) => resp.error_for_status(),
-        Err(err) => bail!("HTTP error at {}: {}", url, err.to_string()),
+        Err(err) => rig_bail!(Network, "HTTP error at {}: {}", url, err.to_string()),
     };
     let body = match body {
         Ok(content) => content,
-        Err(err) => bail!("HTTP error at {}: {}", url, err.to_string()),
+        Err(err) => rig_bail!(Network, "HTTP error at {}: {}", url, err.to_string()),
     };
     let body = body.text().await;
     match body {
         Ok(txt) => Ok(txt),
-        Err(err) => bail!("HTTP error at {}: {}", url, err.to_string()),
+        Err(err) => rig_bail!(Network, "HTTP error at {}: {}", url, err.to_string()),
     }
 }
 
@@ -178,6 +461,11 @@ pub async fn download_json(
 ) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
     let vers: Vec<Result<serde_json::Value, Box<dyn Error>>> =
         future::join_all(urls.into_iter().map(|url| async move {
+            let url = mirror_url(&url)?;
+            if !is_remote_url(&url) {
+                let txt = std::fs::read_to_string(&url)?;
+                return Ok(serde_json::from_str(&txt)?);
+            }
             let json = client
                 .get(url)
                 .send()
@@ -194,7 +482,7 @@ pub async fn download_json(
     for v in vers {
         match v {
             Ok(v) => vers2.push(v),
-            Err(e) => bail!("Cannot download JSON: {}", e.to_string()),
+            Err(e) => return Err(error_context(e, "Cannot download JSON")),
         };
     }
 
@@ -0,0 +1,140 @@
+use std::error::Error;
+use std::fmt;
+
+use regex::Regex;
+use serde_derive::Serialize;
+
+// Errors that wrapper tools might want to handle differently. Every kind
+// has its own exit code, these are documented in the `rig --help` output,
+// so do not change them. All other errors exit with status 1.
+
+#[derive(Debug)]
+pub enum RigError {
+    NotInstalled(String),
+    UnsupportedPlatform(String),
+    Network(String),
+    PermissionDenied(String),
+    InvalidSpec(String),
+    ExternalCommand(String),
+}
+
+impl RigError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RigError::NotInstalled(_) => "not-installed",
+            RigError::UnsupportedPlatform(_) => "unsupported-platform",
+            RigError::Network(_) => "network",
+            RigError::PermissionDenied(_) => "permission-denied",
+            RigError::InvalidSpec(_) => "invalid-spec",
+            RigError::ExternalCommand(_) => "external-command",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RigError::NotInstalled(_) => 10,
+            RigError::UnsupportedPlatform(_) => 11,
+            RigError::Network(_) => 12,
+            RigError::PermissionDenied(_) => 13,
+            RigError::InvalidSpec(_) => 14,
+            RigError::ExternalCommand(_) => 15,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            RigError::NotInstalled(x)
+            | RigError::UnsupportedPlatform(x)
+            | RigError::Network(x)
+            | RigError::PermissionDenied(x)
+            | RigError::InvalidSpec(x)
+            | RigError::ExternalCommand(x) => x,
+        }
+    }
+}
+
+impl fmt::Display for RigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for RigError {}
+
+// Like `bail!`, but with an error kind, e.g.
+// `rig_bail!(NotInstalled, "R {} is not installed", ver)`
+
+macro_rules! rig_bail {
+    ($kind:ident, $($arg:tt)+) => {
+        return Err(::std::convert::From::from(
+            $crate::error::RigError::$kind(format!($($arg)+)),
+        ))
+    };
+}
+pub(crate) use rig_bail;
+
+// The kind of an error, as a constructor. Errors that do not come from
+// `rig_bail!` are classified by their type.
+fn error_variant(err: &(dyn Error + 'static)) -> Option<fn(String) -> RigError> {
+    if let Some(x) = err.downcast_ref::<RigError>() {
+        return Some(match x {
+            RigError::NotInstalled(_) => RigError::NotInstalled,
+            RigError::UnsupportedPlatform(_) => RigError::UnsupportedPlatform,
+            RigError::Network(_) => RigError::Network,
+            RigError::PermissionDenied(_) => RigError::PermissionDenied,
+            RigError::InvalidSpec(_) => RigError::InvalidSpec,
+            RigError::ExternalCommand(_) => RigError::ExternalCommand,
+        });
+    }
+    if err.downcast_ref::<reqwest::Error>().is_some() {
+        return Some(RigError::Network);
+    }
+    if let Some(x) = err.downcast_ref::<std::io::Error>() {
+        if x.kind() == std::io::ErrorKind::PermissionDenied {
+            return Some(RigError::PermissionDenied);
+        }
+    }
+    None
+}
+
+// Add some context to the error message, but keep its kind
+pub fn error_context(err: Box<dyn Error>, context: &str) -> Box<dyn Error> {
+    let msg = format!("{}: {}", context, err);
+    match error_variant(err.as_ref()) {
+        Some(variant) => Box::new(variant(msg)),
+        None => msg.into(),
+    }
+}
+
+pub fn error_kind(err: &(dyn Error + 'static)) -> (&'static str, i32) {
+    match error_variant(err) {
+        Some(variant) => {
+            let err = variant(String::new());
+            (err.kind(), err.exit_code())
+        }
+        None => ("error", 1),
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorOutput {
+    error: &'static str,
+    exit_code: i32,
+    message: String,
+}
+
+pub fn error_json(err: &(dyn Error + 'static)) -> String {
+    let (kind, code) = error_kind(err);
+    // Drop the terminal markup, e.g. <b>...</b>
+    let msg = err.to_string();
+    let msg = match Regex::new("</?[a-z]*>") {
+        Ok(re) => re.replace_all(&msg, "").to_string(),
+        Err(_) => msg,
+    };
+    let out = ErrorOutput {
+        error: kind,
+        exit_code: code,
+        message: msg,
+    };
+    serde_json::to_string(&out).unwrap_or_else(|_| "{}".to_string())
+}
@@ -14,8 +14,17 @@ use simplelog::info;
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use sudo::with_env;
 
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+use crate::error::*;
+
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 pub fn escalate(task: &str) -> Result<(), Box<dyn Error>> {
+    // User installs never need root
+    #[cfg(target_os = "linux")]
+    if crate::linux::is_user_mode() {
+        return Ok(());
+    }
+
     let need_sudo = match sudo::check() {
         sudo::RunningAs::Root => false,
         sudo::RunningAs::User => true,
@@ -35,14 +44,35 @@ pub fn escalate(task: &str) -> Result<(), Box<dyn Error>> {
             "Running `sudo` for {}. This might need your password.",
             task
         );
-        with_env(&["RIG_HOME", "RUST_BACKTRACE"])?;
+        let res = with_env(&[
+            "RIG_",
+            "RUST_BACKTRACE",
+            "HTTP_PROXY",
+            "HTTPS_PROXY",
+            "NO_PROXY",
+            "http_proxy",
+            "https_proxy",
+            "no_proxy",
+        ]);
+        if let Err(err) = res {
+            rig_bail!(PermissionDenied, "Cannot run `sudo` for {}: {}", task, err);
+        }
     }
 
     Ok(())
 }
 
+// If rig was started with `sudo`, then this is the home of the user
+// that called `sudo`, not root's.
+
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 pub fn get_home() -> Result<String, Box<dyn Error>> {
+    let user = crate::utils::get_user()?;
+    if user.sudo {
+        if let Ok(home) = user.dir.into_string() {
+            return Ok(home);
+        }
+    }
     let home = match std::env::var("HOME") {
         Ok(x) => Ok(x),
         Err(_) => {
@@ -16,13 +16,19 @@ use simple_error::bail;
 
 mod alias;
 mod args;
+mod cache;
 mod common;
 mod config;
 mod download;
+mod error;
 mod escalate;
 mod library;
+mod mirror;
+mod output;
 mod macos;
+mod project;
 mod renv;
+mod repos;
 mod resolve;
 mod rversion;
 mod run;
@@ -19,6 +19,7 @@ use crate::linux::*;
 
 use crate::config::*;
 use crate::escalate::*;
+use crate::output::*;
 use crate::rversion::*;
 use crate::utils::*;
 
@@ -40,21 +41,9 @@ pub fn sc_library_ls(
         .collect();
     names.sort();
 
-    if args.is_present("json") || libargs.is_present("json") || mainargs.is_present("json") {
-        println!("[");
-        let num = libs.len();
-        for (idx, lib) in libs.iter().enumerate() {
-            let path = lib.path.display().to_string();
-            println!("  {{");
-            println!("    \"name\": \"{}\",", lib.name);
-            println!("    \"path\": \"{}\",", path.replace("\\", "/"));
-            println!(
-                "    \"default\": {}",
-                if lib.default { "true" } else { "false" }
-            );
-            println!("  }}{}", if idx == num - 1 { "" } else { "," });
-        }
-        println!("]");
+    let format = get_output_format(&[args, libargs, mainargs]);
+    if format != OutputFormat::Text {
+        print_output(format, &libs)?;
     } else {
         for name in names {
             println!("{}", name);
@@ -149,6 +138,11 @@ pub fn sc_library_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
             bail!("Need to set default R version for `rig library`.")
         }
     };
+    library_add(&rver, &new)
+}
+
+pub fn library_add(rver: &str, new: &str) -> Result<(), Box<dyn Error>> {
+    let new = new.to_string();
     let libs = sc_library_get_list(Some(rver.to_string()), false)?;
     let names: Vec<String> = libs.iter().map(|x| x.name.to_owned()).collect();
     if names.contains(&new) {
@@ -165,13 +159,15 @@ pub fn sc_library_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         None => bail!("Internal error, no main library for R {}", rver),
         Some(main) => {
             let dir = main.as_path().join("__".to_string() + &new);
-            try_with!(
-                std::fs::create_dir_all(&dir),
-                "Cannot create directory {} @{}:{}",
-                dir.display(),
-                file!(),
-                line!()
-            );
+            if let Err(err) = create_user_dir_all(&dir) {
+                bail!(
+                    "Cannot create directory {} @{}:{}, {}",
+                    dir.display(),
+                    file!(),
+                    line!(),
+                    err.to_string()
+                );
+            }
         }
     };
 
@@ -229,12 +225,9 @@ pub fn sc_library_default(
         sc_library_set_default(&name)
     } else {
         let default = sc_library_get_default()?;
-        if args.is_present("json") || libargs.is_present("json") || mainargs.is_present("json") {
-            let path = default.path.display().to_string();
-            println!("{{");
-            println!("  \"name\": \"{}\",", default.name);
-            println!("  \"path\": \"{}\"", path.replace("\\", "/"));
-            println!("}}");
+        let format = get_output_format(&[args, libargs, mainargs]);
+        if format != OutputFormat::Text {
+            print_output(format, &default)?;
         } else {
             println!("{}", default.name);
         }
@@ -476,15 +469,22 @@ pub fn get_library_path_cache(rver: &str) -> Result<(PathBuf, PathBuf), Box<dyn
 pub fn get_library_path_nocache(rver: &str) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
     debug!("Finding library path (R {}) without cache", rver);
     let r = get_r_binary(rver)?;
+    let mut cmd = Command::new(r);
+    cmd.args([
+        "--vanilla",
+        "-s",
+        "-e",
+        "cat(strsplit(Sys.getenv('R_LIBS_USER'), .Platform$path.sep)[[1]][1])"
+    ]);
+
+    // After `sudo` we need the library of the user, not root's
+    let home = user_home_after_sudo()?;
+    if let Some(home) = &home {
+        cmd.env("HOME", home);
+    }
+
     let out = try_with!(
-        Command::new(r)
-            .args([
-                "--vanilla",
-                "-s",
-                "-e",
-                "cat(strsplit(Sys.getenv('R_LIBS_USER'), .Platform$path.sep)[[1]][1])"
-            ])
-            .output(),
+        cmd.output(),
         "Failed to run R {} to get library path @{}:{}",
         rver,
         file!(),
@@ -499,7 +499,10 @@ pub fn get_library_path_nocache(rver: &str) -> Result<(PathBuf, PathBuf), Box<dy
         ),
     };
 
-    let defaultstr = shellexpand::tilde(&lib.as_str()).to_string();
+    let defaultstr = match &home {
+        Some(home) => shellexpand::tilde_with_context(&lib.as_str(), || Some(home)).to_string(),
+        None => shellexpand::tilde(&lib.as_str()).to_string(),
+    };
     let default = Path::new(&defaultstr);
     let mut main = Path::new(&defaultstr);
 
@@ -543,3 +546,14 @@ pub fn get_library_path_nocache(rver: &str) -> Result<(PathBuf, PathBuf), Box<dy
     );
     Ok((main.to_path_buf(), default.to_path_buf()))
 }
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn user_home_after_sudo() -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let user = get_user()?;
+    Ok(if user.sudo { Some(PathBuf::from(user.dir)) } else { None })
+}
+
+#[cfg(target_os = "windows")]
+fn user_home_after_sudo() -> Result<Option<PathBuf>, Box<dyn Error>> {
+    Ok(None)
+}
@@ -4,23 +4,30 @@ use regex::Regex;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::ffi::OsString;
-use std::os::unix::fs::symlink;
+use std::os::unix::fs::{symlink, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::{file, line};
 
 use clap::ArgMatches;
+use serde_derive::Serialize;
 use simple_error::*;
 use simplelog::{trace,debug, info, warn};
+use tabular::*;
 
-use crate::resolve::resolve_versions;
+use crate::resolve::{expand_url, resolve_versions};
 use crate::rversion::*;
 
 use crate::alias::*;
+use crate::cache::*;
 use crate::common::*;
-use crate::download::*;
+use crate::config::*;
+use crate::distros::*;
+use crate::error::*;
 use crate::escalate::*;
 use crate::library::*;
+use crate::output::*;
+use crate::repos::*;
 use crate::run::*;
 use crate::utils::*;
 
@@ -28,45 +35,73 @@ pub const R_ROOT: &str = "/opt/R";
 pub const R_VERSIONDIR: &str = "{}";
 pub const R_SYSLIBPATH: &str = "{}/lib/R/library";
 pub const R_BINPATH: &str = "{}/bin/R";
-const R_CUR: &str = "/opt/R/current";
-
-#[cfg(target_arch = "x86_64")]
-const UBUNTU_1804_URL: &str = "https://cdn.rstudio.com/r/ubuntu-1804/pkgs/r-{}_1_amd64.deb";
-#[cfg(target_arch = "x86_64")]
-const UBUNTU_2004_URL: &str = "https://cdn.rstudio.com/r/ubuntu-2004/pkgs/r-{}_1_amd64.deb";
-#[cfg(target_arch = "x86_64")]
-const UBUNTU_2204_URL: &str = "https://cdn.rstudio.com/r/ubuntu-2204/pkgs/r-{}_1_amd64.deb";
-#[cfg(target_arch = "x86_64")]
-const DEBIAN_9_URL: &str = "https://cdn.rstudio.com/r/debian-9/pkgs/r-{}_1_amd64.deb";
-#[cfg(target_arch = "x86_64")]
-const DEBIAN_10_URL: &str = "https://cdn.rstudio.com/r/debian-10/pkgs/r-{}_1_amd64.deb";
-#[cfg(target_arch = "x86_64")]
-const DEBIAN_11_URL: &str = "https://cdn.rstudio.com/r/debian-11/pkgs/r-{}_1_amd64.deb";
-
-#[cfg(target_arch = "aarch64")]
-const UBUNTU_1804_URL: &str =
-    "https://github.com/r-hub/R/releases/download/v{}/R-rstudio-ubuntu-1804-{}_1_arm64.deb";
-#[cfg(target_arch = "aarch64")]
-const UBUNTU_2004_URL: &str =
-    "https://github.com/r-hub/R/releases/download/v{}/R-rstudio-ubuntu-2004-{}_1_arm64.deb";
-#[cfg(target_arch = "aarch64")]
-const UBUNTU_2204_URL: &str =
-    "https://github.com/r-hub/R/releases/download/v{}/R-rstudio-ubuntu-2204-{}_1_arm64.deb";
-#[cfg(target_arch = "aarch64")]
-const DEBIAN_9_URL: &str =
-    "https://github.com/r-hub/R/releases/download/v{}/R-rstudio-debian-9-{}_1_arm64.deb";
-#[cfg(target_arch = "aarch64")]
-const DEBIAN_10_URL: &str =
-    "https://github.com/r-hub/R/releases/download/v{}/R-rstudio-debian-10-{}_1_arm64.deb";
-#[cfg(target_arch = "aarch64")]
-const DEBIAN_11_URL: &str =
-    "https://github.com/r-hub/R/releases/download/v{}/R-rstudio-debian-11-{}_1_arm64.deb";
-
-const UBUNTU_1804_RSPM: &str = "https://packagemanager.rstudio.com/all/__linux__/bionic/latest";
-const UBUNTU_2004_RSPM: &str = "https://packagemanager.rstudio.com/all/__linux__/focal/latest";
-const UBUNTU_2204_RSPM: &str = "https://packagemanager.rstudio.com/all/__linux__/jammy/latest";
-
-pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+
+const SOURCE_URL: &str = "{cran}/src/base/R-{major}/R-{}.tar.gz";
+const SOURCE_DEVEL_URL: &str = "{cran}/src/base-prerelease/R-devel.tar.gz";
+const SOURCE_NEXT_URL: &str = "{cran}/src/base-prerelease/R-latest.tar.gz";
+
+const EPEL_URL: &str = "https://dl.fedoraproject.org/pub/epel/epel-release-latest-{}.noarch.rpm";
+
+// In user mode (`--user` or `RIG_USER=true`) R versions are installed
+// into the rig data directory, quick links go to `~/.local/bin`, and rig
+// never calls sudo.
+
+pub fn is_user_mode() -> bool {
+    match std::env::var("RIG_USER") {
+        Ok(x) => x == "true" || x == "1" || x == "yes",
+        Err(_) => false,
+    }
+}
+
+// All R roots, the first one is where new R versions are installed.
+// This is `/opt/R`, unless `r-root` is configured.
+
+pub fn get_r_roots() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if is_user_mode() {
+        return Ok(vec![rig_config_dir()?.join("R")]);
+    }
+    match get_r_root_config()? {
+        Some(x) => Ok(x),
+        None => Ok(vec![PathBuf::from(R_ROOT)]),
+    }
+}
+
+pub fn get_r_root() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_r_roots()?[0].to_owned())
+}
+
+// The root that has this R version, or the main root, if none has it
+pub fn get_r_version_root(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let roots = get_r_roots()?;
+    for root in &roots {
+        if root.join(rver).join("bin/R").exists() {
+            return Ok(root.to_owned());
+        }
+    }
+    Ok(roots[0].to_owned())
+}
+
+// Binary packages install into /opt/R, in other roots we extract and
+// relocate them.
+fn is_default_root() -> Result<bool, Box<dyn Error>> {
+    Ok(!is_user_mode() && get_r_root()? == Path::new(R_ROOT))
+}
+
+pub fn get_r_current() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_r_root()?.join("current"))
+}
+
+pub fn get_quick_link_dir() -> Result<PathBuf, Box<dyn Error>> {
+    if !is_user_mode() {
+        return Ok(PathBuf::from("/usr/local/bin"));
+    }
+    match directories::BaseDirs::new().and_then(|x| x.executable_dir().map(|p| p.to_path_buf())) {
+        Some(x) => Ok(x),
+        None => bail!("Cannot determine user bin directory, is HOME set?"),
+    }
+}
+
+pub fn add_r(opts: &AddOptions) -> Result<(), Box<dyn Error>> {
     escalate("adding new R versions")?;
 
     // This is needed to fix statix linking on Arm Linux :(
@@ -75,38 +110,39 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         println!("{}", uid);
     }
 
-    let linux = detect_linux()?;
-    let version = get_resolve(args)?;
-    let alias = get_alias(args);
-    let ver = version.version.to_owned();
-    let verstr = match ver {
-        Some(ref x) => x,
-        None => "???",
-    };
-
-    let url: String = match &version.url {
-        Some(s) => s.to_string(),
-        None => bail!("Cannot find a download url for R version {}", verstr),
+    let from_source = opts.from_source;
+    let linux = match detect_linux() {
+        Ok(x) => Some(x),
+        Err(err) => {
+            if !from_source {
+                bail!(
+                    "{}. Use `rig add --from-source` to build R from source.",
+                    err.to_string()
+                );
+            }
+            debug!("{}, building from source", err.to_string());
+            None
+        }
     };
-
-    let filename = basename(&url).unwrap_or_else(|| "foo");
-    let tmp_dir = std::env::temp_dir().join("rig");
-    let target = tmp_dir.join(&filename);
-    if target.exists() && not_too_old(&target) {
-        info!("{} is cached at {}", filename, target.display());
-    } else {
-        info!("Downloading {} -> {}", url, target.display());
-        let client = &reqwest::Client::new();
-        download_file(client, &url, &target.as_os_str())?;
+    let alias = get_alias(&opts.spec);
+
+    if !is_default_root()? && !from_source {
+        if let Some(linux) = &linux {
+            if linux.installer != "deb" {
+                bail!(
+                    "User installs, and installs outside of {}, need a deb based \
+                     distro for binary R builds, use `--from-source` to build R \
+                     from source",
+                    R_ROOT
+                );
+            }
+        }
     }
 
-    let dirname;
-    if linux.distro == "ubuntu" || linux.distro == "debian" {
-        add_deb(&target.as_os_str())?;
-        dirname = get_install_dir_deb(&target.as_os_str())?;
-    } else {
-        bail!("Only Ubuntu and Debian Linux are supported currently");
-    }
+    let dirname = match &linux {
+        Some(linux) if !from_source => add_binary(opts, linux)?,
+        _ => add_source(opts)?,
+    };
 
     set_default_if_none(dirname.to_string())?;
 
@@ -117,30 +153,156 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         None => { }
     };
 
-    if !args.is_present("without-cran-mirror") {
+    if !opts.without("cran-mirror")? {
         set_cloud_mirror(Some(vec![dirname.to_string()]))?;
     }
 
-    if !args.is_present("without-rspm") {
-        set_rspm(Some(vec![dirname.to_string()]), &linux)?;
-    }
+    if let Some(linux) = &linux {
+        if !opts.without("rspm")? {
+            set_rspm(Some(vec![dirname.to_string()]), linux)?;
+        }
 
-    if !args.is_present("without-sysreqs") {
-        set_sysreqs(Some(vec![dirname.to_string()]), &linux)?;
+        if is_user_mode() {
+            debug!("Skipping sysreqs setup, it needs root");
+        } else if !opts.without("sysreqs")? {
+            set_sysreqs(Some(vec![dirname.to_string()]), linux)?;
+        }
     }
 
-    if !args.is_present("without-pak") {
+    if !opts.without("pak")? {
         system_add_pak(
             Some(vec![dirname.to_string()]),
-            require_with!(args.value_of("pak-version"), "clap error"),
+            &opts.pak_version()?,
             // If this is specified then we always re-install
-            args.occurrences_of("pak-version") > 0,
+            opts.pak_version.is_some(),
         )?;
     }
 
     Ok(())
 }
 
+fn add_binary(opts: &AddOptions, linux: &LinuxVersion) -> Result<String, Box<dyn Error>> {
+    let (_version, target) = opts.download()?;
+
+    let dirname;
+    if linux.installer == "deb" && !is_default_root()? {
+        dirname = get_install_dir_deb(target.as_os_str())?;
+        add_deb_extract(target.as_os_str(), &dirname)?;
+    } else if linux.installer == "deb" {
+        add_deb(&target.as_os_str())?;
+        dirname = get_install_dir_deb(&target.as_os_str())?;
+    } else if linux.installer == "rpm" {
+        add_rpm(target.as_os_str(), linux)?;
+        dirname = get_install_dir_rpm(target.as_os_str())?;
+    } else if linux.installer == "zypper" {
+        add_zypper(target.as_os_str())?;
+        dirname = get_install_dir_rpm(target.as_os_str())?;
+    } else {
+        bail!(
+            "Unknown installer for {} {}: {}",
+            linux.distro,
+            linux.version,
+            linux.installer
+        );
+    }
+
+    Ok(dirname)
+}
+
+fn add_source(opts: &AddOptions) -> Result<String, Box<dyn Error>> {
+    let version = get_resolve_source(&opts.spec)?;
+    let verstr = match version.version {
+        Some(ref x) => x.to_string(),
+        None => "???".to_string(),
+    };
+
+    // This is the version string (or `devel`, `next`), see source_linux()
+    let dirname = match &version.url {
+        Some(s) => s.to_string(),
+        None => bail!("Cannot find a source tarball for R version {}", verstr),
+    };
+    let url = get_source_url(&dirname)?;
+
+    let filename = "R-".to_string() + &dirname + ".tar.gz";
+    let target = cache_download(
+        &url,
+        &filename,
+        version.version.to_owned(),
+        None,
+        cache_max_age(&dirname),
+        opts.sha256.as_deref(),
+        true,
+    )?;
+    let tmp_dir = std::env::temp_dir().join("rig");
+
+    let builddir = tmp_dir.join("build-".to_string() + &dirname);
+    if builddir.exists() {
+        std::fs::remove_dir_all(&builddir)?;
+    }
+    std::fs::create_dir_all(&builddir)?;
+
+    info!("Extracting {}", filename);
+    let args: Vec<OsString> = vec![
+        os("xzf"),
+        target.into_os_string(),
+        os("-C"),
+        builddir.to_owned().into_os_string(),
+    ];
+    run("tar".into(), args, "tar")?;
+
+    // The tarball has a single directory, e.g. R-4.2.1 or R-devel
+    let srcdir = match std::fs::read_dir(&builddir)?.next() {
+        Some(x) => x?.path(),
+        None => bail!("Source tarball {} is empty", filename),
+    };
+
+    let prefix = get_r_root()?.join(&dirname);
+    info!("Configuring R {} with prefix {}", verstr, prefix.display());
+    let mut prefixarg = os("--prefix=");
+    prefixarg.push(prefix.as_os_str());
+    let args: Vec<OsString> = vec![prefixarg, os("--enable-R-shlib")];
+    if let Err(err) = run_in_dir(&srcdir, srcdir.join("configure").into(), args, "configure") {
+        bail!(
+            "Failed to configure R {}, are all build dependencies installed? {}",
+            verstr,
+            err.to_string()
+        );
+    }
+
+    let ncpus = std::thread::available_parallelism().map_or(1, |x| x.get());
+    info!("Building R {} (this will take a while)", verstr);
+    let args: Vec<OsString> = vec![os(&format!("-j{}", ncpus))];
+    run_in_dir(&srcdir, "make".into(), args, "make")?;
+
+    info!("Installing R {} into {}", verstr, prefix.display());
+    let args: Vec<OsString> = vec![os("install")];
+    run_in_dir(&srcdir, "make".into(), args, "make install")?;
+
+    if let Err(err) = std::fs::remove_dir_all(&builddir) {
+        warn!(
+            "Failed to remove build directory {}: {}",
+            builddir.display(),
+            err.to_string()
+        );
+    }
+
+    Ok(dirname)
+}
+
+fn get_source_url(ver: &str) -> Result<String, Box<dyn Error>> {
+    if ver == "devel" {
+        expand_url(SOURCE_DEVEL_URL)
+    } else if ver == "next" {
+        expand_url(SOURCE_NEXT_URL)
+    } else {
+        let major = match ver.split('.').next() {
+            Some(x) => x,
+            None => rig_bail!(InvalidSpec, "Invalid R version: {}", ver),
+        };
+        Ok(expand_url(SOURCE_URL)?.replace("{major}", major).replace("{}", ver))
+    }
+}
+
 fn get_install_dir_deb(path: &OsStr) -> Result<String, Box<dyn Error>> {
     let path2 = Path::new(path);
     let out = try_with!(
@@ -184,42 +346,280 @@ fn add_deb(path: &OsStr) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    escalate("removing R versions")?;
-    let vers = args.values_of("version");
-    if vers.is_none() {
-        return Ok(());
+// Extract the package into the R root, and replace the
+// `/opt/R/<version>` paths in it. Used for user installs and other roots.
+
+fn add_deb_extract(path: &OsStr, dirname: &str) -> Result<(), Box<dyn Error>> {
+    let root = get_r_root()?;
+    let staging = root.join(".staging-".to_string() + dirname);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
     }
-    let vers = require_with!(vers, "clap error");
+    std::fs::create_dir_all(&staging)?;
 
-    for ver in vers {
-        let ver = check_installed(&ver.to_string())?;
+    info!("Extracting {}", Path::new(path).display());
+    let args: Vec<OsString> = vec![os("-x"), path.to_os_string(), staging.to_owned().into_os_string()];
+    run("dpkg-deb".into(), args, "dpkg-deb -x")?;
 
-        let pkgname = "r-".to_string() + &ver;
-        let out = try_with!(
-            Command::new("dpkg").args(["-s", &pkgname]).output(),
-            "Failed to run dpkg -s {} @{}:{}",
-            pkgname,
-            file!(),
-            line!()
+    let src = staging.join("opt/R").join(dirname);
+    if !src.exists() {
+        bail!("Unexpected package layout, no /opt/R/{} in {}", dirname, Path::new(path).display());
+    }
+    let target = root.join(dirname);
+    if target.exists() {
+        std::fs::remove_dir_all(&target)?;
+    }
+    std::fs::rename(&src, &target)?;
+    std::fs::remove_dir_all(&staging)?;
+
+    info!("Relocating R {} to {}", dirname, target.display());
+    relocate_tree(&Path::new(R_ROOT).join(dirname), &target, &target)?;
+
+    // Rscript has the R home compiled in, but it uses RHOME if it is set
+    for dir in [target.join("bin"), target.join("lib/R/bin")] {
+        let rscript = dir.join("Rscript");
+        if !rscript.exists() || !is_binary_file(&rscript)? {
+            continue;
+        }
+        let real = dir.join("Rscript.bin");
+        std::fs::rename(&rscript, &real)?;
+        let wrapper = format!(
+            "#!/bin/sh\nRHOME='{}' exec '{}' \"$@\"\n",
+            target.join("lib/R").display(),
+            real.display()
         );
+        std::fs::write(&rscript, wrapper)?;
+        std::fs::set_permissions(&rscript, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    info!(
+        "rig does not install the system dependencies of R for this install, \
+         if R fails to start, install the dependencies of the r-{} package",
+        dirname
+    );
+
+    Ok(())
+}
+
+fn is_binary_file(path: &Path) -> Result<bool, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    Ok(bytes.contains(&0))
+}
+
+// Replace `old` with `new` in the text files and symlinks under `dir`
+fn relocate_tree(old: &Path, new: &Path, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let oldstr = old.display().to_string();
+    let newstr = new.display().to_string();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let meta = std::fs::symlink_metadata(&path)?;
+        if meta.file_type().is_symlink() {
+            let target = std::fs::read_link(&path)?;
+            if let Ok(rest) = target.strip_prefix(old) {
+                std::fs::remove_file(&path)?;
+                symlink(new.join(rest), &path)?;
+            }
+        } else if meta.is_dir() {
+            relocate_tree(old, new, &path)?;
+        } else if meta.is_file() {
+            let bytes = std::fs::read(&path)?;
+            if bytes.contains(&0) {
+                continue;
+            }
+            if let Ok(text) = String::from_utf8(bytes) {
+                if text.contains(&oldstr) {
+                    trace!("Relocating {}", path.display());
+                    std::fs::write(&path, text.replace(&oldstr, &newstr))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_install_dir_rpm(path: &OsStr) -> Result<String, Box<dyn Error>> {
+    let path2 = Path::new(path);
+    let out = try_with!(
+        Command::new("rpm")
+            .args(["-q", "-p", "--queryformat", "%{NAME}"])
+            .arg(path)
+            .output(),
+        "Failed to run rpm -qp {} @{}:{}",
+        path2.display(),
+        file!(),
+        line!()
+    );
+    let std = try_with!(
+        String::from_utf8(out.stdout),
+        "Non-UTF-8 output from rpm -qp {} @{}:{}",
+        path2.display(),
+        file!(),
+        line!()
+    );
+    let re = Regex::new("^R-(.*)$")?;
+    let name = std.trim();
+    if !re.is_match(name) {
+        bail!("Unexpected package name in {}: {}", path2.display(), name);
+    }
+    let ver = re.replace(name, "${1}");
+
+    Ok(ver.to_string())
+}
+
+fn rpm_package_manager() -> &'static str {
+    if Path::new("/usr/bin/dnf").exists() {
+        "dnf"
+    } else {
+        "yum"
+    }
+}
+
+fn add_rpm(path: &OsStr, linux: &LinuxVersion) -> Result<(), Box<dyn Error>> {
+    let pm = rpm_package_manager();
+
+    // The RHEL builds need some dependencies from EPEL
+    if linux.distro == "rhel" {
+        add_epel(linux)?;
+    }
+
+    info!("Running {} install", pm);
+    let args: Vec<OsString> = vec![os("install"), os("-y"), path.to_os_string()];
+    run(pm.into(), args, &format!("{} install", pm))?;
+
+    Ok(())
+}
 
-        if out.status.success() {
-            info!("Removing {} package", pkgname);
-	    let mut args: Vec<OsString> = vec![];
-	    args.push(os("remove"));
-	    args.push(os("-y"));
-	    // https://askubuntu.com/a/668859
-	    args.push(os("-o=Dpkg::Use-Pty=0"));
-	    args.push(os("--purge"));
-	    args.push(os(&pkgname));
-	    run("apt-get".into(), args, "apt-get remove")?;
+fn add_epel(linux: &LinuxVersion) -> Result<(), Box<dyn Error>> {
+    let out = try_with!(
+        Command::new("rpm").args(["-q", "epel-release"]).output(),
+        "Failed to run rpm -q epel-release @{}:{}",
+        file!(),
+        line!()
+    );
+    if out.status.success() {
+        debug!("EPEL is already enabled");
+        return Ok(());
+    }
+
+    info!("Enabling the EPEL repository");
+    let pm = rpm_package_manager();
+    let args: Vec<OsString> = vec![
+        os("install"),
+        os("-y"),
+        os(&EPEL_URL.replace("{}", &linux.version)),
+    ];
+    run(pm.into(), args, &format!("{} install epel-release", pm))?;
+
+    // Some EPEL packages need the CodeReady Builder (crb), called
+    // PowerTools on RHEL 8 derivatives. This is not fatal, as it is not
+    // needed for all R builds, and RHEL itself uses subscription-manager.
+    let crb = if linux.version == "8" { "powertools" } else { "crb" };
+    if linux.version != "7" {
+        let args: Vec<OsString> = vec![os("config-manager"), os("--set-enabled"), os(crb)];
+        if let Err(err) = run("dnf".into(), args, "dnf config-manager") {
+            warn!("Failed to enable the {} repository: {}", crb, err.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn add_zypper(path: &OsStr) -> Result<(), Box<dyn Error>> {
+    info!("Running zypper install");
+    let args: Vec<OsString> = vec![
+        os("--non-interactive"),
+        // The r-builds RPMs are not signed
+        os("--no-gpg-checks"),
+        os("install"),
+        os("--allow-unsigned-rpm"),
+        path.to_os_string(),
+    ];
+    run("zypper".into(), args, "zypper install")?;
+
+    Ok(())
+}
+
+fn rm_deb(ver: &str) -> Result<(), Box<dyn Error>> {
+    let pkgname = "r-".to_string() + ver;
+    let out = try_with!(
+        Command::new("dpkg").args(["-s", &pkgname]).output(),
+        "Failed to run dpkg -s {} @{}:{}",
+        pkgname,
+        file!(),
+        line!()
+    );
+
+    if out.status.success() {
+        info!("Removing {} package", pkgname);
+        let args: Vec<OsString> = vec![
+            os("remove"),
+            os("-y"),
+            // https://askubuntu.com/a/668859
+            os("-o=Dpkg::Use-Pty=0"),
+            os("--purge"),
+            os(&pkgname),
+        ];
+        run("apt-get".into(), args, "apt-get remove")?;
+    } else {
+        info!("{} package is not installed", pkgname);
+    }
+
+    Ok(())
+}
+
+// `pm` is dnf, yum or zypper
+fn rm_rpm(ver: &str, pm: &str) -> Result<(), Box<dyn Error>> {
+    let pkgname = "R-".to_string() + ver;
+    let out = try_with!(
+        Command::new("rpm").args(["-q", &pkgname]).output(),
+        "Failed to run rpm -q {} @{}:{}",
+        pkgname,
+        file!(),
+        line!()
+    );
+
+    if out.status.success() {
+        info!("Removing {} package", pkgname);
+        let mut args: Vec<OsString> = vec![];
+        if pm == "zypper" {
+            args.push(os("--non-interactive"));
+            args.push(os("remove"));
         } else {
-            info!("{} package is not installed", pkgname);
+            args.push(os("remove"));
+            args.push(os("-y"));
         }
+        args.push(os(&pkgname));
+        run(pm.into(), args, &format!("{} remove", pm))?;
+    } else {
+        info!("{} package is not installed", pkgname);
+    }
+
+    Ok(())
+}
 
-        let dir = Path::new(R_ROOT);
-        let dir = dir.join(&ver);
+pub fn rm_r(vers: &[String]) -> Result<(), Box<dyn Error>> {
+    escalate("removing R versions")?;
+    // On unsupported distros R was built from source, no package to remove
+    let installer = match detect_linux() {
+        Ok(linux) => linux.installer,
+        Err(_) => "source".to_string(),
+    };
+
+    for ver in vers {
+        let ver = check_installed(ver)?;
+
+        let root = get_r_version_root(&ver)?;
+        if is_user_mode() || root != Path::new(R_ROOT) {
+            debug!("R {} is in {}, no package to remove", ver, root.display());
+        } else if installer == "deb" {
+            rm_deb(&ver)?;
+        } else if installer == "rpm" {
+            rm_rpm(&ver, rpm_package_manager())?;
+        } else if installer == "zypper" {
+            rm_rpm(&ver, "zypper")?;
+        }
+
+        let dir = root.join(&ver);
         if dir.exists() {
             info!("Removing {}", dir.display());
             try_with!(
@@ -230,6 +630,7 @@ pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
                 line!()
             );
         }
+        remove_version_aliases(&ver)?;
     }
 
     sc_system_make_links()?;
@@ -240,21 +641,44 @@ pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
 pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
     escalate("making R-* quick links")?;
     let vers = sc_get_list()?;
-    let base = Path::new(R_ROOT);
+    let roots = get_r_roots()?;
+    let bindir = get_quick_link_dir()?;
+    let tools = get_quick_links()?;
+    std::fs::create_dir_all(&bindir)?;
 
     // Create new links
     for ver in vers {
-        let linkfile = Path::new("/usr/local/bin").join("R-".to_string() + &ver);
-        let target = base.join(&ver).join("bin/R");
-        if !linkfile.exists() {
-            info!("Adding {} -> {}", linkfile.display(), target.display());
-            symlink(&target, &linkfile)?;
+        for tool in &tools {
+            let linkfile = bindir.join(tool.to_owned() + "-" + &ver);
+            let target = get_r_version_root(&ver)?.join(&ver).join("bin").join(tool);
+            if !target.exists() {
+                debug!("No {} in R {}, no quick link", tool, ver);
+                continue;
+            }
+            // A dangling link, e.g. to a version that was removed from
+            // another root
+            if !linkfile.exists() && std::fs::symlink_metadata(&linkfile).is_ok() {
+                std::fs::remove_file(&linkfile)?;
+            }
+            if !linkfile.exists() {
+                info!("Adding {} -> {}", linkfile.display(), target.display());
+                symlink(&target, &linkfile)?;
+            }
         }
     }
 
-    // Remove dangling links
-    let paths = std::fs::read_dir("/usr/local/bin")?;
-    let re = Regex::new("^R-([0-9]+[.][0-9]+[.][0-9]+|oldrel|next|release|devel)$")?;
+    // Remove dangling links, also for tools that are not configured
+    // any more, but only if they point into the R root
+    let paths = std::fs::read_dir(&bindir)?;
+    let mut aliases: Vec<String> = vec![
+        "[0-9]+[.][0-9]+[.][0-9]+".to_string(),
+        "oldrel".to_string(),
+        "next".to_string(),
+        "release".to_string(),
+        "devel".to_string(),
+    ];
+    aliases.extend(get_config_aliases()?.keys().map(|x| regex::escape(x)));
+    let re = Regex::new(&("^[A-Za-z0-9_]+-(".to_string() + &aliases.join("|") + ")$"))?;
     for file in paths {
         let path = file?.path();
         // If no path name, then path ends with ..., so we can skip
@@ -269,9 +693,13 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
         };
         if re.is_match(&fnamestr) {
             match std::fs::read_link(&path) {
-                Err(_) => warn!("<magenra>[WARN]</> {} is not a symlink", path.display()),
+                Err(_) => {
+                    if fnamestr.starts_with("R-") {
+                        warn!("<magenra>[WARN]</> {} is not a symlink", path.display())
+                    }
+                }
                 Ok(target) => {
-                    if !target.exists() {
+                    if !target.exists() && roots.iter().any(|r| target.starts_with(r)) {
                         info!("Cleaning up {}", target.display());
                         match std::fs::remove_file(&path) {
                             Err(err) => {
@@ -295,7 +723,11 @@ pub fn re_alias() -> Regex {
 pub fn find_aliases() -> Result<Vec<Alias>, Box<dyn Error>> {
     debug!("Finding existing aliases");
 
-    let paths = std::fs::read_dir("/usr/local/bin")?;
+    let bindir = get_quick_link_dir()?;
+    if !bindir.exists() {
+        return Ok(vec![]);
+    }
+    let paths = std::fs::read_dir(bindir)?;
     let re = re_alias();
     let mut result: Vec<Alias> = vec![];
 
@@ -358,23 +790,65 @@ fn version_from_link(pb: PathBuf) -> Option<String> {
 }
 
 pub fn get_resolve(args: &ArgMatches) -> Result<Rversion, Box<dyn Error>> {
-    let str = args
-        .value_of("str")
-        .ok_or(SimpleError::new("Internal argument error"))?;
+    Ok(get_resolve_all(args)?[0].to_owned())
+}
 
-    let eps = vec![str.to_string()];
+pub fn get_resolve_all(args: &ArgMatches) -> Result<Vec<Rversion>, Box<dyn Error>> {
+    let eps: Vec<String> = args
+        .values_of("str")
+        .ok_or(SimpleError::new("Internal argument error"))?
+        .map(|x| x.to_string())
+        .collect();
+    get_resolve_specs(eps)
+}
+
+pub fn get_resolve_specs(eps: Vec<String>) -> Result<Vec<Rversion>, Box<dyn Error>> {
     let me = detect_linux()?;
+    resolve_versions(eps, "linux".to_string(), "default".to_string(), Some(me))
+}
+
+// For source builds we use a fake distro with an URL template that is
+// just the version, so the resolved `url` is the R version, or `devel`
+// or `next`, and this is also the name of the installation directory.
+
+fn source_linux() -> LinuxVersion {
+    LinuxVersion {
+        distro: "source".to_string(),
+        version: "".to_string(),
+        url: "{}".to_string(),
+        rspm: false,
+        rspm_url: "".to_string(),
+        installer: "source".to_string(),
+    }
+}
+
+fn get_resolve_source(str: &str) -> Result<Rversion, Box<dyn Error>> {
+    let eps = vec![str.to_string()];
+    let me = source_linux();
     let version = resolve_versions(eps, "linux".to_string(), "default".to_string(), Some(me))?;
     Ok(version[0].to_owned())
 }
 
 pub fn sc_get_list() -> Result<Vec<String>, Box<dyn Error>> {
-    let mut vers = Vec::new();
-    if !Path::new(R_ROOT).exists() {
-        return Ok(vers);
+    let mut vers: Vec<String> = Vec::new();
+    for root in get_r_roots()? {
+        if !root.exists() {
+            continue;
+        }
+        for name in sc_get_list_root(&root)? {
+            // The first root wins, if a version is in more than one
+            if !vers.contains(&name) {
+                vers.push(name);
+            }
+        }
     }
+    vers.sort();
+    Ok(vers)
+}
 
-    let paths = std::fs::read_dir(R_ROOT)?;
+fn sc_get_list_root(root: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut vers = Vec::new();
+    let paths = std::fs::read_dir(root)?;
 
     for de in paths {
         let path = de?.path();
@@ -407,40 +881,44 @@ pub fn sc_set_default(ver: &str) -> Result<(), Box<dyn Error>> {
     escalate("setting the default R version")?;
     let ver = check_installed(&ver.to_string())?;
 
+    let current = get_r_current()?;
+    let bindir = get_quick_link_dir()?;
+    std::fs::create_dir_all(&bindir)?;
+
     // Remove current link
-    if Path::new(R_CUR).exists() {
-        std::fs::remove_file(R_CUR)?;
+    if std::fs::symlink_metadata(&current).is_ok() {
+        std::fs::remove_file(&current)?;
     }
 
     // Add current link
-    let path = Path::new(R_ROOT).join(ver);
-    std::os::unix::fs::symlink(&path, R_CUR)?;
+    let path = get_r_version_root(&ver)?.join(&ver);
+    std::os::unix::fs::symlink(&path, &current)?;
 
-    // Remove /usr/local/bin/R link
-    let r = Path::new("/usr/local/bin/R");
-    if r.exists() {
-        std::fs::remove_file(r)?;
+    // Remove R link in the quick link directory
+    let r = bindir.join("R");
+    if std::fs::symlink_metadata(&r).is_ok() {
+        std::fs::remove_file(&r)?;
     }
 
-    // Add /usr/local/bin/R link
-    let cr = Path::new("/opt/R/current/bin/R");
+    // Add R link in the quick link directory
+    let cr = current.join("bin/R");
     std::os::unix::fs::symlink(&cr, &r)?;
 
-    // Remove /usr/local/bin/Rscript link
-    let rs = Path::new("/usr/local/bin/Rscript");
-    if rs.exists() {
-        std::fs::remove_file(rs)?;
+    // Remove Rscript link in the quick link directory
+    let rs = bindir.join("Rscript");
+    if std::fs::symlink_metadata(&rs).is_ok() {
+        std::fs::remove_file(&rs)?;
     }
 
-    // Add /usr/local/bin/Rscript link
-    let crs = Path::new("/opt/R/current/bin/Rscript");
+    // Add Rscript link in the quick link directory
+    let crs = current.join("bin/Rscript");
     std::os::unix::fs::symlink(&crs, &rs)?;
 
     Ok(())
 }
 
 pub fn sc_get_default() -> Result<Option<String>, Box<dyn Error>> {
-    read_version_link(R_CUR)
+    read_version_link(&get_r_current()?.display().to_string())
 }
 
 fn set_cloud_mirror(vers: Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
@@ -448,21 +926,18 @@ fn set_cloud_mirror(vers: Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
         Some(x) => x,
         None => sc_get_list()?,
     };
+    let mirror = get_config_string("cran-mirror")?;
 
     info!("Setting default CRAN mirror");
 
     for ver in vers {
         let ver = check_installed(&ver)?;
-        let path = Path::new(R_ROOT).join(ver.as_str());
-        let profile = path.join("lib/R/library/base/R/Rprofile".to_string());
+        let profile = get_system_profile(&ver)?;
         if !profile.exists() {
             continue;
         }
 
-        append_to_file(
-            &profile,
-            vec!["options(repos = c(CRAN = \"https://cloud.r-project.org\"))".to_string()],
-        )?;
+        set_repo_setting(&profile, RepoSetting::Cran, Some(&mirror))?;
     }
     Ok(())
 }
@@ -489,22 +964,14 @@ fn set_rspm(vers: Option<Vec<String>>, linux: &LinuxVersion) -> Result<(), Box<d
         None => sc_get_list()?,
     };
 
-    let rcode = r#"
-options(repos = c(RSPM="%url%", getOption("repos")))
-options(HTTPUserAgent = sprintf("R/%s R (%s)", getRversion(), paste(getRversion(), R.version$platform, R.version$arch, R.version$os)))
-"#;
-
-    let rcode = rcode.to_string().replace("%url%", &linux.rspm_url);
-
     for ver in vers {
         let ver = check_installed(&ver)?;
-        let path = Path::new(R_ROOT).join(ver.as_str());
-        let profile = path.join("lib/R/library/base/R/Rprofile".to_string());
+        let profile = get_system_profile(&ver)?;
         if !profile.exists() {
             continue;
         }
 
-        append_to_file(&profile, vec![rcode.to_string()])?;
+        set_repo_setting(&profile, RepoSetting::Rspm, Some(&linux.rspm_url))?;
     }
     Ok(())
 }
@@ -525,23 +992,38 @@ fn set_sysreqs(vers: Option<Vec<String>>, linux: &LinuxVersion) -> Result<(), Bo
         None => sc_get_list()?,
     };
 
-    let rcode = r#"
-Sys.setenv(PKG_SYSREQS = "true")
-"#;
-
     for ver in vers {
         let ver = check_installed(&ver)?;
-        let path = Path::new(R_ROOT).join(ver.as_str());
-        let profile = path.join("lib/R/library/base/R/Rprofile".to_string());
+        let profile = get_system_profile(&ver)?;
         if !profile.exists() {
             continue;
         }
 
-        append_to_file(&profile, vec![rcode.to_string()])?;
+        set_repo_setting(&profile, RepoSetting::Sysreqs, Some("true"))?;
     }
     Ok(())
 }
 
+// The value that `rig add` sets up, `None` if it does not set up this
+// setting on this platform. On unsupported distros only the CRAN
+// mirror is set up, by `rig add --from-source`.
+pub fn default_repo_setting(setting: RepoSetting) -> Result<Option<String>, Box<dyn Error>> {
+    if setting == RepoSetting::Cran {
+        return Ok(Some(get_config_string("cran-mirror")?));
+    }
+    let linux = match detect_linux() {
+        Ok(x) => x,
+        Err(_) => return Ok(None),
+    };
+    Ok(match setting {
+        RepoSetting::Rspm if linux.rspm && std::env::consts::ARCH == "x86_64" => Some(linux.rspm_url),
+        RepoSetting::Sysreqs if linux.distro == "ubuntu" && linux.rspm && !is_user_mode() => {
+            Some("true".to_string())
+        }
+        _ => None,
+    })
+}
+
 pub fn sc_system_allow_core_dumps(_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     // Nothing to do on Linux
     Ok(())
@@ -577,7 +1059,7 @@ pub fn sc_system_no_openmp(_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn detect_linux() -> Result<LinuxVersion, Box<dyn Error>> {
+pub fn detect_linux() -> Result<LinuxVersion, Box<dyn Error>> {
     let release_file = Path::new("/etc/os-release");
     let lines = read_lines(release_file)?;
 
@@ -601,20 +1083,12 @@ fn detect_linux() -> Result<LinuxVersion, Box<dyn Error>> {
         unquote(&ver)
     };
 
-    let mut mine = LinuxVersion {
-        distro: id.to_owned(),
-        version: ver.to_owned(),
-        url: "".to_string(),
-        rspm: false,
-        rspm_url: "".to_string(),
-    };
-
-    debug!("Detected distro: {} {}", mine.distro, mine.version);
+    debug!("Detected distro: {} {}", id, ver);
 
     // Maybe Deepin?
     if id == "Deepin" {
 	let debverfile = Path::new("/etc/debian_version");
-	let mut ver = "unknown".to_string();
+	ver = "unknown".to_string();
 	if debverfile.exists() {
 	    let lines = read_lines(debverfile)?;
 	    if lines.len() > 0 {
@@ -622,109 +1096,79 @@ fn detect_linux() -> Result<LinuxVersion, Box<dyn Error>> {
 		ver = re_ver.replace(&lines[0], "").to_string();
 	    }
 	}
-	mine.distro = "debian".to_string();
-	mine.version = ver;
+	id = "debian".to_string();
     }
 
-    let supported = list_supported_distros();
-
-    let mut good = false;
-    for dis in &supported {
-        if dis.distro == mine.distro && dis.version == mine.version {
-            mine.url = dis.url.to_owned();
-            mine.rspm = dis.rspm.to_owned();
-            mine.rspm_url = dis.rspm_url.to_owned();
-            good = true;
-        }
+    if let Some(mine) = match_distro(&id, &ver)? {
+        debug!("Using builds for {} {}", mine.distro, mine.version);
+        return Ok(mine);
     }
 
     // Maybe an Ubuntu-like distro
-    if !good {
-	debug!("Unsupported distro, checking if an Ubuntu derivative");
-        let re_codename = Regex::new("^UBUNTU_CODENAME=")?;
-        let codename_line = grep_lines(&re_codename, &lines);
-        if codename_line.len() != 0 {
-            let codename_line = &lines[codename_line[0]];
-            let codename = re_codename.replace(&codename_line, "").to_string();
-
-            (id, ver) = match &codename[..] {
-                "bionic" => ("ubuntu".to_string(), "18.04".to_string()),
-                "focal" => ("ubuntu".to_string(), "20.04".to_string()),
-                "jammy" => ("ubuntu".to_string(), "22.04".to_string()),
-                _ => ("".to_string(), "".to_string()),
-            };
-
-            mine.distro = id.to_owned();
-            mine.version = ver.to_owned();
-            for dis in &supported {
-                if dis.distro == mine.distro && dis.version == mine.version {
-                    mine.url = dis.url.to_owned();
-                    mine.rspm = dis.rspm.to_owned();
-                    mine.rspm_url = dis.rspm_url.to_owned();
-                    good = true;
-		    debug!("Distro derivative of {} {}", id, ver);
-                }
-            }
+    debug!("Unsupported distro, checking if an Ubuntu derivative");
+    let re_codename = Regex::new("^UBUNTU_CODENAME=")?;
+    let codename_line = grep_lines(&re_codename, &lines);
+    if codename_line.len() != 0 {
+        let codename_line = &lines[codename_line[0]];
+        let codename = re_codename.replace(&codename_line, "").to_string();
+        let codename = unquote(&codename);
+        if let Some(mine) = match_distro_codename(&codename)? {
+            debug!("Distro derivative of {} {}", mine.distro, mine.version);
+            return Ok(mine);
         }
     }
 
-    if !good {
-        bail!(
-            "Unsupported distro: {} {}, only {} are supported currently",
-            &id,
-            &ver,
-            "Ubuntu 18.04, 20.04, 22.04 and Debian 9-11"
-        );
+    let supported = list_supported_distros()?;
+    let supp: Vec<String> = supported
+        .iter()
+        .map(|x| x.distro.to_owned() + " " + &x.version)
+        .collect();
+    rig_bail!(
+        UnsupportedPlatform,
+        "Unsupported distro: {} {}, only {} are supported currently",
+        &id,
+        &ver,
+        supp.join(", ")
+    );
+}
+
+#[derive(Serialize)]
+struct DistroEntry {
+    #[serde(flatten)]
+    distro: LinuxVersion,
+    current: bool,
+}
+
+pub fn sc_system_distros(args: &ArgMatches, mainargs: &ArgMatches)
+                         -> Result<(), Box<dyn Error>> {
+    let (_, source) = load_distro_manifest()?;
+    let supported = list_supported_distros()?;
+    let current = detect_linux().ok();
+
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        let out: Vec<DistroEntry> = supported
+            .into_iter()
+            .map(|dis| DistroEntry {
+                current: current.as_ref() == Some(&dis),
+                distro: dis,
+            })
+            .collect();
+        print_output(format, &out)?;
+    } else {
+        info!("Distro manifest: {}", source);
+        let mut tab = Table::new("{:<}  {:<}  {:<}  {:<}  {:<}");
+        tab.add_row(row!["current", "distro", "version", "installer", "rspm"]);
+        tab.add_heading("------------------------------------------");
+        for dis in supported {
+            let cur = if current.as_ref() == Some(&dis) { "*" } else { " " };
+            let rspm = if dis.rspm { "yes" } else { "no" };
+            tab.add_row(row!(cur, dis.distro, dis.version, dis.installer, rspm));
+        }
+        print!("{}", tab);
     }
 
-    Ok(mine)
-}
-
-fn list_supported_distros() -> Vec<LinuxVersion> {
-    vec![
-        LinuxVersion {
-            distro: "ubuntu".to_string(),
-            version: "18.04".to_string(),
-            url: UBUNTU_1804_URL.to_string(),
-            rspm: true,
-            rspm_url: UBUNTU_1804_RSPM.to_string(),
-        },
-        LinuxVersion {
-            distro: "ubuntu".to_string(),
-            version: "20.04".to_string(),
-            url: UBUNTU_2004_URL.to_string(),
-            rspm: true,
-            rspm_url: UBUNTU_2004_RSPM.to_string(),
-        },
-        LinuxVersion {
-            distro: "ubuntu".to_string(),
-            version: "22.04".to_string(),
-            url: UBUNTU_2204_URL.to_string(),
-            rspm: true,
-            rspm_url: UBUNTU_2204_RSPM.to_string(),
-        },
-        LinuxVersion {
-            distro: "debian".to_string(),
-            version: "9".to_string(),
-            url: DEBIAN_9_URL.to_string(),
-            rspm: false,
-            rspm_url: "".to_string(),
-        },
-        LinuxVersion {
-            distro: "debian".to_string(),
-            version: "10".to_string(),
-            url: DEBIAN_10_URL.to_string(),
-            rspm: false,
-            rspm_url: "".to_string(),
-        },
-        LinuxVersion {
-            distro: "debian".to_string(),
-            version: "11".to_string(),
-            url: DEBIAN_11_URL.to_string(),
-            rspm: false,
-            rspm_url: "".to_string(),
-        },
-    ]
+    Ok(())
 }
 
 pub fn sc_clean_registry() -> Result<(), Box<dyn Error>> {
@@ -748,7 +1192,7 @@ pub fn sc_rstudio_(version: Option<&str>, project: Option<&str>, arg: Option<&Os
     if let Some(ver) = version {
         let ver = check_installed(&ver.to_string())?;
         envname = "RSTUDIO_WHICH_R";
-        path = R_ROOT.to_string() + "/" + &ver + "/bin/R"
+        path = get_r_binary(&ver)?.display().to_string()
     };
 
     if let Some(arg) = arg {
@@ -770,19 +1214,25 @@ pub fn sc_rstudio_(version: Option<&str>, project: Option<&str>, arg: Option<&Os
 
 pub fn get_r_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     debug!("Finding R binary for R {}", rver);
-    let bin = Path::new(R_ROOT).join(rver).join("bin/R");
+    let bin = get_r_version_root(rver)?.join(rver).join("bin/R");
     debug!("R {} binary is at {}", rver, bin.display());
     Ok(bin)
 }
 
+pub fn get_rscript_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let bin = get_r_version_root(rver)?.join(rver).join("bin/Rscript");
+    debug!("R {} Rscript is at {}", rver, bin.display());
+    Ok(bin)
+}
+
 #[allow(dead_code)]
 pub fn get_system_renviron(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
-    let renviron = Path::new(R_ROOT).join(rver).join("lib/R/etc/Renviron");
+    let renviron = get_r_version_root(rver)?.join(rver).join("lib/R/etc/Renviron");
     Ok(renviron)
 }
 
 pub fn get_system_profile(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
-    let profile = Path::new(R_ROOT)
+    let profile = get_r_version_root(rver)?
         .join(rver)
         .join("lib/R/library/base/R/Rprofile");
     Ok(profile)
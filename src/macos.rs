@@ -16,9 +16,12 @@ use simplelog::{debug, info, warn};
 
 use crate::alias::*;
 use crate::common::*;
+use crate::config::*;
 use crate::download::*;
+use crate::error::*;
 use crate::escalate::*;
 use crate::library::*;
+use crate::repos::*;
 use crate::resolve::resolve_versions;
 use crate::rversion::*;
 use crate::run::*;
@@ -30,42 +33,13 @@ pub const R_SYSLIBPATH: &str = "{}/Resources/library";
 pub const R_BINPATH: &str = "{}/Resources/R";
 const R_CUR: &str = "/Library/Frameworks/R.framework/Versions/Current";
 
-pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+pub fn add_r(opts: &AddOptions) -> Result<(), Box<dyn Error>> {
     escalate("adding new R versions")?;
-    let mut version = get_resolve(args)?;
-    let alias = get_alias(args);
+    let (mut version, target) = opts.download()?;
+    let alias = get_alias(&opts.spec);
     let ver = version.version.to_owned();
-    let verstr = match ver {
-        Some(ref x) => x,
-        None => "???",
-    };
-    let url: String = match &version.url {
-        Some(s) => s.to_string(),
-        None => {
-            let archarg = args
-                .value_of("arch")
-                .ok_or(SimpleError::new("Internal argument error"))?;
-            bail!("Cannot find a download url for R version {}, {}", verstr, archarg);
-        }
-    };
     let arch = version.arch.to_owned();
-    let prefix = match arch {
-        Some(ref x) => x.to_owned(),
-        None => calculate_hash(&url),
-    };
-    let filename = prefix + "-" + basename(&url).unwrap_or("foo");
-    let tmp_dir = std::env::temp_dir().join("rig");
-    let target = tmp_dir.join(&filename);
-    let cache = target.exists() && not_too_old(&target);
     let target_str = target.to_owned().into_os_string();
-    let target_dsp = target.display();
-    if cache {
-        info!("{} is cached at {}", filename, target_dsp);
-    } else {
-        info!("Downloading {} -> {}", url, target_dsp);
-        let client = &reqwest::Client::new();
-        download_file(client, &url, &target_str)?;
-    }
 
     sc_system_forget()?;
 
@@ -98,17 +72,16 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         None => { }
     };
 
-    if !args.is_present("without-cran-mirror") {
+    if !opts.without("cran-mirror")? {
         set_cloud_mirror(Some(vec![dirname.to_string()]))?;
     }
 
-    if !args.is_present("without-pak") {
+    if !opts.without("pak")? {
         system_add_pak(
             Some(vec![dirname.to_string()]),
-            args.value_of("pak-version")
-                .ok_or(SimpleError::new("Internal argument error"))?,
+            &opts.pak_version()?,
             // If this is specified then we always re-install
-            args.occurrences_of("pak-version") > 0,
+            opts.pak_version.is_some(),
         )?;
     }
 
@@ -235,18 +208,13 @@ fn safe_install(target: std::path::PathBuf, ver: &str, arch: Option<String>)
     Ok(())
 }
 
-pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+pub fn rm_r(vers: &[String]) -> Result<(), Box<dyn Error>> {
     escalate("removing R versions")?;
-    let vers = args.values_of("version");
-    if vers.is_none() {
-        return Ok(());
-    }
-    let vers = vers.ok_or(SimpleError::new("Internal argument error"))?;
     let default = sc_get_default()?;
 
     for ver in vers {
 
-        let ver = check_installed(&ver.to_string())?;
+        let ver = check_installed(ver)?;
 
         if let Some(ref default) = default {
             if default == &ver {
@@ -263,6 +231,7 @@ pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
             Err(err) => bail!("Cannot remove {}: {}", dir.display(), err.to_string()),
             _ => {}
         };
+        remove_version_aliases(&ver)?;
     }
 
     sc_system_make_links()?;
@@ -274,30 +243,41 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
     escalate("making R-* quick links")?;
     let vers = sc_get_list()?;
     let base = Path::new(R_ROOT);
+    let tools = get_quick_links()?;
 
     info!("Updating R-* quick links (as needed)");
 
     // Create new links
     for ver in vers {
-        let linkfile = Path::new("/usr/local/bin/").join("R-".to_string() + &ver);
-        let target = base.join(&ver).join("Resources/bin/R");
-        if !linkfile.exists() {
-            debug!("Adding {} -> {}", linkfile.display(), target.display());
-            match symlink(&target, &linkfile) {
-                Err(err) => bail!(
-                    "Cannot create symlink {}: {}",
-                    linkfile.display(),
-                    err.to_string()
-                ),
-                _ => {}
-            };
+        for tool in &tools {
+            let linkfile = Path::new("/usr/local/bin/").join(tool.to_owned() + "-" + &ver);
+            let target = base.join(&ver).join("Resources/bin").join(tool);
+            if !target.exists() {
+                debug!("No {} in R {}, no quick link", tool, ver);
+                continue;
+            }
+            if !linkfile.exists() {
+                debug!("Adding {} -> {}", linkfile.display(), target.display());
+                match symlink(&target, &linkfile) {
+                    Err(err) => bail!(
+                        "Cannot create symlink {}: {}",
+                        linkfile.display(),
+                        err.to_string()
+                    ),
+                    _ => {}
+                };
+            }
         }
     }
 
-    // Remove dangling links
+    // Remove dangling links, also for tools that are not configured
+    // any more, but only if they point into R_ROOT
     let paths = std::fs::read_dir("/usr/local/bin")?;
-    let re = Regex::new("^R-[0-9]+[.][0-9]+")?;
-    let re2 = re_alias();
+    let re = Regex::new("^[A-Za-z0-9_]+-[0-9]+[.][0-9]+")?;
+    let mut aliases: Vec<String> =
+        vec!["next".to_string(), "devel".to_string(), "release".to_string(), "oldrel".to_string()];
+    aliases.extend(get_config_aliases()?.keys().map(|x| regex::escape(x)));
+    let re2 = Regex::new(&("^[A-Za-z0-9_]+-(".to_string() + &aliases.join("|") + ")$"))?;
     for file in paths {
         let path = file?.path();
         // If no path name, then path ends with ..., so we can skip
@@ -314,7 +294,7 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
             match std::fs::read_link(&path) {
                 Err(_) => debug!("{} is not a symlink", path.display()),
                 Ok(target) => {
-                    if !target.exists() {
+                    if !target.exists() && target.starts_with(base) {
                         debug!("Cleaning up {}", target.display());
                         match std::fs::remove_file(&path) {
                             Err(err) => {
@@ -442,7 +422,7 @@ pub fn sc_system_allow_debugger_rstudio(_args: &ArgMatches) -> Result<(), Box<dy
         join("/Applications/RStudio.app/Contents/MacOS/rsession");
 
     if !rsess.exists() {
-        bail!("RStudio is not installed, at least not in /Applications/RStudio.app");
+        rig_bail!(NotInstalled, "RStudio is not installed, at least not in /Applications/RStudio.app");
     }
 
     update_entitlements(rsess)?;
@@ -704,25 +684,49 @@ pub fn sc_system_forget() -> Result<(), Box<dyn Error>> {
 }
 
 pub fn get_resolve(args: &ArgMatches) -> Result<Rversion, Box<dyn Error>> {
-    let str = args
-        .value_of("str")
+    Ok(get_resolve_all(args)?[0].to_owned())
+}
+
+pub fn get_resolve_all(args: &ArgMatches) -> Result<Vec<Rversion>, Box<dyn Error>> {
+    let strs: Vec<String> = args
+        .values_of("str")
         .ok_or(SimpleError::new("Internal argument error"))?
-        .to_string();
+        .map(|x| x.to_string())
+        .collect();
     let arch = args
         .value_of("arch")
         .ok_or(SimpleError::new("Internal argument error"))?;
+    get_resolve_specs(strs, arch)
+}
 
-    if str.len() > 8 && (&str[..7] == "http://" || &str[..8] == "https://") {
-        Ok(Rversion {
-            version: None,
-            url: Some(str.to_string()),
-            arch: None,
-        })
+fn is_url(str: &str) -> bool {
+    str.len() > 8 && (&str[..7] == "http://" || &str[..8] == "https://")
+}
+
+// Installer URLs are used as is, the rest are resolved together
+pub fn get_resolve_specs(strs: Vec<String>, arch: &str) -> Result<Vec<Rversion>, Box<dyn Error>> {
+    let eps: Vec<String> = strs.iter().filter(|x| !is_url(x)).cloned().collect();
+    let resolved = if eps.is_empty() {
+        vec![]
     } else {
-        let eps = vec![str];
-        let version = resolve_versions(eps, "macos".to_string(), arch.to_string(), None)?;
-        Ok(version[0].to_owned())
+        resolve_versions(eps, "macos".to_string(), arch.to_string(), None)?
+    };
+    let mut resolved = resolved.into_iter();
+
+    let mut versions = vec![];
+    for str in strs {
+        if is_url(&str) {
+            versions.push(Rversion {
+                version: None,
+                url: Some(str.to_string()),
+                arch: None,
+                sha256: None,
+            });
+        } else {
+            versions.push(require_with!(resolved.next(), "internal error"));
+        }
     }
+    Ok(versions)
 }
 
 pub fn sc_system_no_openmp(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -770,24 +774,21 @@ fn set_cloud_mirror(vers: Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
         Some(x) => x,
         None => sc_get_list()?,
     };
+    let mirror = get_config_string("cran-mirror")?;
 
     info!("Setting default CRAN mirror");
 
     for ver in vers {
         let ver = check_installed(&ver)?;
-        let path = Path::new(R_ROOT).join(ver.as_str());
-        let profile = path.join("Resources/library/base/R/Rprofile".to_string());
+        let profile = get_system_profile(&ver)?;
         if !profile.exists() {
             continue;
         }
 
-        match append_to_file(
-            &profile,
-            vec!["options(repos = c(CRAN = \"https://cloud.r-project.org\"))".to_string()],
-        ) {
+        match set_repo_setting(&profile, RepoSetting::Cran, Some(&mirror)) {
             Ok(_) => {}
             Err(err) => {
-                bail!("Failed to update {}: {}", path.display(), err);
+                bail!("Failed to update {}: {}", profile.display(), err);
             }
         };
     }
@@ -795,6 +796,15 @@ fn set_cloud_mirror(vers: Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// The value that `rig add` sets up, `None` if it does not set up this
+// setting on this platform
+pub fn default_repo_setting(setting: RepoSetting) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(match setting {
+        RepoSetting::Cran => Some(get_config_string("cran-mirror")?),
+        _ => None,
+    })
+}
+
 pub fn sc_clean_registry() -> Result<(), Box<dyn Error>> {
     // Nothing to do on macOS
     Ok(())
@@ -938,7 +948,7 @@ fn get_install_dir(ver: &Rversion) -> Result<String, Box<dyn Error>> {
     } else if arch == "arm64" {
         Ok(minor + "-arm64")
     } else {
-        bail!("Unknown macOS arch: {}", arch);
+        rig_bail!(UnsupportedPlatform, "Unknown macOS arch: {}", arch);
     }
 }
 
@@ -977,11 +987,33 @@ fn extract_pkg_version(filename: &OsStr) -> Result<Rversion, Box<dyn Error>> {
         version: Some(ver.to_string()),
         url: None,
         arch: Some(arch.to_string()),
+        sha256: None,
     };
 
     Ok(res)
 }
 
+pub fn get_r_root() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(PathBuf::from(R_ROOT))
+}
+
+// The R installer decides where R goes, so there is only one root here
+pub fn get_r_roots() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    Ok(vec![get_r_root()?])
+}
+
+pub fn get_r_version_root(_rver: &str) -> Result<PathBuf, Box<dyn Error>> {
+    get_r_root()
+}
+
+pub fn get_r_current() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(PathBuf::from(R_CUR))
+}
+
+pub fn get_quick_link_dir() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(PathBuf::from("/usr/local/bin"))
+}
+
 pub fn get_r_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     debug!("Finding R binary for R {}", rver);
     let bin = Path::new(R_ROOT).join(rver).join("Resources/R");
@@ -989,6 +1021,12 @@ pub fn get_r_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     Ok(bin)
 }
 
+pub fn get_rscript_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let bin = Path::new(R_ROOT).join(rver).join("Resources/bin/Rscript");
+    debug!("R {} Rscript is at {}", rver, bin.display());
+    Ok(bin)
+}
+
 #[allow(dead_code)]
 pub fn get_system_renviron(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     let renviron = Path::new(R_ROOT).join(rver).join("Resources/etc/Renviron");
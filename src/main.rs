@@ -1,6 +1,8 @@
 use std::error::Error;
+use std::path::PathBuf;
 
 use clap::ArgMatches;
+use serde_derive::Serialize;
 use simple_error::*;
 use simplelog::*;
 use tabular::*;
@@ -18,27 +20,53 @@ mod windows;
 #[cfg(target_os = "windows")]
 use windows::*;
 
+#[cfg(target_os = "linux")]
+mod distros;
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
 use linux::*;
 
 mod alias;
+mod cache;
 mod library;
+mod mirror;
+mod output;
+mod project;
 mod common;
 mod config;
+mod doctor;
 mod download;
+mod error;
 mod renv;
+mod repos;
 mod resolve;
 mod rversion;
 mod run;
+mod shell;
+mod sync;
 mod sysreqs;
+mod upgrade;
 mod utils;
 
+use alias::sc_alias;
+use cache::*;
+use config::sc_config;
+use doctor::*;
+use download::parse_sha256;
 use library::*;
+use mirror::*;
+use project::*;
+use repos::sc_system_repos;
+use shell::*;
+use sync::*;
 use sysreqs::*;
+use upgrade::*;
 
 use crate::common::*;
+use crate::error::*;
+use crate::output::*;
+use crate::rversion::{InstalledVersion, Rversion};
 
 mod escalate;
 
@@ -84,33 +112,104 @@ fn main_() -> i32 {
 
     // --------------------------------------------------------------------
 
+    #[cfg(target_os = "linux")]
+    if let Some(manifest) = args.value_of("distro-manifest") {
+        std::env::set_var("RIG_DISTRO_MANIFEST", manifest);
+    }
+
+    #[cfg(target_os = "linux")]
+    if args.is_present("user") {
+        std::env::set_var("RIG_USER", "true");
+    }
+
+    config::export_config_env();
+
     match main__(&args) {
-        Ok(_) => {
-            return 0;
-        }
+        Ok(_) => 0,
         Err(err) => {
-            error!("{}", err.to_string());
-            return 1;
+            let (_, code) = error_kind(err.as_ref());
+            if is_json_output(&args) {
+                eprintln!("{}", error_json(err.as_ref()));
+            } else {
+                error!("{}", err.to_string());
+            }
+            code
+        }
+    }
+}
+
+// `--json` can be given to the command, or to any of its parent commands,
+// but not all commands have it, and clap panics if we ask for an
+// argument that a command does not have.
+
+fn is_json_output(args: &ArgMatches) -> bool {
+    let mut cmd = rig_app();
+    let mut cur = args;
+    loop {
+        if cur.value_of("format") == Some("json") {
+            return true;
+        }
+        if cmd.get_arguments().any(|x| x.get_id() == "json") && cur.is_present("json") {
+            return true;
+        }
+        match cur.subcommand() {
+            Some((name, sub)) => {
+                cmd = match cmd.find_subcommand(name) {
+                    Some(x) => x.clone(),
+                    None => return false,
+                };
+                cur = sub;
+            }
+            None => return false,
         }
     }
 }
 
 fn main__(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     match args.subcommand() {
-        Some(("add", sub)) => sc_add(sub),
+        Some(("add", sub)) => {
+            // Check this before downloading anything
+            if let Some(x) = sub.value_of("sha256") {
+                if parse_sha256(x).is_none() {
+                    rig_bail!(InvalidSpec, "Invalid SHA-256 digest: {}", x);
+                }
+            }
+            // Picked up by `verify_download()`
+            if sub.is_present("no-verify") {
+                std::env::set_var("RIG_VERIFY_DOWNLOAD", "false");
+            }
+            if sub.is_present("from-project") {
+                sc_add_from_project(sub)
+            } else if sub.occurrences_of("str") > 1 {
+                sc_add_multiple(sub)
+            } else {
+                sc_add(sub)
+            }
+        }
+        Some(("alias", sub)) => sc_alias(sub, args),
+        Some(("cache", sub)) => sc_cache(sub, args),
+        Some(("config", sub)) => sc_config(sub, args),
         Some(("default", sub)) => sc_default(sub, args),
+        Some(("doctor", sub)) => sc_doctor(sub, args),
         Some(("list", sub)) => sc_list(sub, args),
         Some(("rm", sub)) => sc_rm(sub),
-        Some(("system", sub)) => sc_system(sub),
+        Some(("system", sub)) => sc_system(sub, args),
         Some(("resolve", sub)) => sc_resolve(sub, args),
         Some(("rstudio", sub)) => sc_rstudio(sub),
+        Some(("run", sub)) => sc_run(sub),
+        Some(("shell-init", sub)) => sc_shell_init(sub),
+        Some(("sync", sub)) => sc_sync(sub),
+        Some(("upgrade", sub)) => sc_upgrade(sub),
+        Some(("use", sub)) => sc_use(sub),
+        Some(("which", sub)) => sc_which(sub, args),
         Some(("library", sub)) => sc_library(sub, args),
+        Some(("mirror", sub)) => sc_mirror(sub),
         Some(("sysreqs", sub)) => sc_sysreqs(sub, args),
         _ => Ok(()), // unreachable
     }
 }
 
-fn sc_system(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+fn sc_system(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     match args.subcommand() {
         Some(("add-pak", s)) => sc_system_add_pak(s),
         Some(("allow-core-dumps", s)) => sc_system_allow_core_dumps(s),
@@ -118,11 +217,14 @@ fn sc_system(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         Some(("allow-debugger-rstudio", s)) => sc_system_allow_debugger_rstudio(s),
         Some(("clean-registry", _)) => sc_clean_registry(),
         Some(("create-lib", s)) => sc_system_create_lib(s),
+        #[cfg(target_os = "linux")]
+        Some(("distros", s)) => sc_system_distros(s, mainargs),
         Some(("make-links", _)) => sc_system_make_links(),
         Some(("make-orthogonal", s)) => sc_system_make_orthogonal(s),
         Some(("fix-permissions", s)) => sc_system_fix_permissions(s),
         Some(("forget", _)) => sc_system_forget(),
         Some(("no-openmp", s)) => sc_system_no_openmp(s),
+        Some(("repos", s)) => sc_system_repos(s, mainargs),
 	Some(("update-rtools40", _)) => sc_system_update_rtools40(),
         _ => Ok(()), // unreachable
     }
@@ -142,6 +244,11 @@ fn sc_library(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Er
 
 fn sc_resolve(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let version = get_resolve(args)?;
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        return print_output(format, &vec![version]);
+    }
+
     let url: String = match version.url {
         Some(s) => s.to_string(),
         None => "NA".to_string(),
@@ -150,64 +257,240 @@ fn sc_resolve(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Er
         Some(s) => s.to_string(),
         None => "???".to_string(),
     };
+    println!("{} {}", version, url);
 
-    if args.is_present("json") || mainargs.is_present("json") {
-        println!("[");
-        println!("  {{");
-        println!("     \"version\": \"{}\",", version);
-        println!("     \"url\": \"{}\"", url);
-        println!("  }}");
-        println!("]");
-    } else {
-        println!("{} {}", version, url);
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+
+fn sc_add_from_project(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let pin = get_project_pin_or_fail()?;
+    if let Some(inst) = match_pin_installed(&pin.spec)? {
+        info!(
+            "R {} is already installed, as required by {}",
+            inst.name,
+            pin.file.display()
+        );
+        return Ok(());
+    }
+    info!("Installing R {}, as required by {}", pin.spec, pin.file.display());
+    add_r(&AddOptions::from_args(args, &pin.spec))
+}
+
+fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let spec = require_with!(args.value_of("str"), "clap error");
+    add_r(&AddOptions::from_args(args, spec))
+}
+
+fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let vers: Vec<String> = match args.values_of("version") {
+        Some(x) => x.map(|x| x.to_string()).collect(),
+        None => return Ok(()),
+    };
+    rm_r(&vers)
+}
+
+// `rig add` with multiple versions: resolve them together, download the
+// installers in parallel, then install them one by one, because the
+// package managers (and the installers) cannot run in parallel. The
+// versions are not resolved again, so moving targets, e.g. `devel`,
+// install the build that was downloaded.
+
+fn sc_add_multiple(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if args.is_present("sha256") {
+        bail!("`--sha256` only works when installing a single R version");
+    }
+    escalate::escalate("adding new R versions")?;
+
+    let specs: Vec<String> = require_with!(args.values_of("str"), "clap error")
+        .map(|x| x.to_string())
+        .collect();
+    let opts: Vec<AddOptions> = specs
+        .iter()
+        .map(|spec| AddOptions::from_args(args, spec))
+        .collect();
+    let downloads = download_installers(&opts);
+
+    let mut results: Vec<(String, Result<String, String>)> = vec![];
+    for (opts, download) in opts.into_iter().zip(downloads) {
+        let spec = opts.spec.to_string();
+        let opts = match download {
+            Ok(x) => x,
+            Err(err) => {
+                results.push((spec, Err(err)));
+                continue;
+            }
+        };
+        let version = match &opts.version {
+            Some(Rversion { version: Some(x), .. }) => x.to_string(),
+            _ => spec.to_string(),
+        };
+        info!("Installing R {}", spec);
+        let res = match add_r(&opts) {
+            Ok(_) => Ok(version),
+            Err(err) => {
+                error!("Failed to install R {}: {}", spec, err.to_string());
+                Err(err.to_string())
+            }
+        };
+        results.push((spec, res));
+    }
+
+    let mut tab = Table::new("{:<}  {:<}  {:<}");
+    tab.add_row(row!["version", "status", "R version / error"]);
+    tab.add_heading("------------------------------------------");
+    let mut failed = 0;
+    for (spec, res) in &results {
+        match res {
+            Ok(new) => tab.add_row(row!(spec, "ok", new)),
+            Err(err) => {
+                failed += 1;
+                tab.add_row(row!(spec, "failed", err))
+            }
+        };
+    }
+    print!("{}", tab);
+
+    if failed > 0 {
+        bail!("Failed to install {} of {} R versions", failed, results.len());
     }
 
     Ok(())
 }
 
+// Download (or find in the cache) the installers of all versions at
+// once, and return the options with the resolved versions and the
+// installers. Source builds download their sources later.
+
+fn download_installers(opts: &[AddOptions]) -> Vec<Result<AddOptions, String>> {
+    #[cfg(target_os = "linux")]
+    if opts.iter().any(|x| x.from_source) {
+        return opts.iter().map(|x| Ok(x.to_owned())).collect();
+    }
+
+    // If one of them fails, resolve them one by one, to see which one
+    let specs: Vec<String> = opts.iter().map(|x| x.spec.to_string()).collect();
+    let versions: Vec<Result<Rversion, String>> = match opts[0].resolve_specs(specs) {
+        Ok(x) => x.into_iter().map(Ok).collect(),
+        Err(_) => opts
+            .iter()
+            .map(|x| match x.resolve_specs(vec![x.spec.to_string()]) {
+                Ok(v) => Ok(v[0].to_owned()),
+                Err(err) => Err(err.to_string()),
+            })
+            .collect(),
+    };
+    let opts: Vec<Result<AddOptions, String>> = opts
+        .iter()
+        .zip(versions)
+        .map(|(x, version)| {
+            let version = version?;
+            if version.url.is_none() {
+                return Err(format!("Cannot find a download url for R {}", x.spec));
+            }
+            Ok(AddOptions { version: Some(version), ..x.to_owned() })
+        })
+        .collect();
+
+    // Download each installer once, e.g. `4.1` and `4.1.3` are the same,
+    // concurrent downloads of the same file would clobber each other
+    let url = |x: &AddOptions| x.version.as_ref().and_then(|v| v.url.to_owned());
+    let mut unique: Vec<&AddOptions> = vec![];
+    for x in opts.iter().flatten() {
+        if !unique.iter().any(|u| url(u) == url(x)) {
+            unique.push(x);
+        }
+    }
+
+    let downloads: Vec<Result<(Rversion, PathBuf), String>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = unique
+            .iter()
+            .map(|x| scope.spawn(move || x.download().map_err(|err| err.to_string())))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| match h.join() {
+                Ok(x) => x,
+                Err(_) => Err("Download failed, internal error".to_string()),
+            })
+            .collect()
+    });
+
+    opts.iter()
+        .map(|x| {
+            let x = x.to_owned()?;
+            match unique.iter().position(|u| url(u) == url(&x)) {
+                Some(idx) => {
+                    let (_, installer) = downloads[idx].to_owned()?;
+                    Ok(AddOptions { installer: Some(installer), ..x })
+                }
+                None => Err("Download failed, internal error".to_string()),
+            }
+        })
+        .collect()
+}
+
 // ------------------------------------------------------------------------
 
+#[derive(Serialize)]
+struct ListEntry {
+    #[serde(flatten)]
+    version: InstalledVersion,
+    default: bool,
+    pinned: bool,
+}
+
+fn find_pinned_installed() -> Result<Option<String>, Box<dyn Error>> {
+    Ok(match find_project_pin()? {
+        None => None,
+        Some(pin) => match_pin_installed(&pin.spec)?.map(|x| x.name),
+    })
+}
+
 fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let vers = sc_get_list_details()?;
     let def = match sc_get_default()? {
         None => "".to_string(),
         Some(v) => v,
     };
-
-    fn or_null(x: &Option<String>) -> String {
-        match x {
-            None => "null".to_string(),
-            Some(x) => x.to_string()
+    // A broken pin file should not break `rig list`
+    let pinned = match find_pinned_installed() {
+        Ok(x) => x.unwrap_or_default(),
+        Err(err) => {
+            warn!("Cannot find the R version of the project: {}", err);
+            "".to_string()
         }
-    }
+    };
 
-    if args.is_present("json") || mainargs.is_present("json") {
-        println!("[");
-        let num = vers.len();
-        for (idx, ver) in vers.iter().enumerate() {
-            let dflt = if def == ver.name { "true" } else { "false" };
-            let alsq: Vec<String> = ver.aliases.iter()
-                .map(|v| "\"".to_string() + v + "\"")
-                .collect();
-            let als = "[".to_string() + &alsq.join(", ") + "]";
-            println!("  {{");
-            println!("    \"name\": \"{}\",", ver.name);
-            println!("    \"default\": {},", dflt);
-            println!("    \"version\": \"{}\",", or_null(&ver.version));
-            println!("    \"aliases\": {},", als);
-            println!("    \"path\": \"{}\",", or_null(&ver.path));
-            println!("    \"binary\": \"{}\"", or_null(&ver.binary));
-            println!("  }}{}", if idx == num - 1 { "" } else { "," });
-        }
-        println!("]");
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        let out: Vec<ListEntry> = vers
+            .into_iter()
+            .map(|ver| ListEntry {
+                default: def == ver.name,
+                pinned: pinned == ver.name,
+                version: ver,
+            })
+            .collect();
+        print_output(format, &out)?;
     } else {
 
-        let mut tab = Table::new("{:<} {:<}  {:<}  {:<}");
-        tab.add_row(row!["*", "name", "version", "aliases"]);
+        // Only show the roots if there are more than one
+        let showroot = get_r_roots()?.len() > 1;
+        let mut tab = if showroot {
+            let mut tab = Table::new("{:<} {:<}  {:<}  {:<}  {:<}");
+            tab.add_row(row!["*", "name", "version", "aliases", "root"]);
+            tab
+        } else {
+            let mut tab = Table::new("{:<} {:<}  {:<}  {:<}");
+            tab.add_row(row!["*", "name", "version", "aliases"]);
+            tab
+        };
         tab.add_heading("------------------------------------------");
         for ver in vers {
             let dflt = if def == ver.name { "*" } else { " " };
-            let note = match ver.version {
+            let mut note = match ver.version {
                 None => "(broken?)".to_string(),
                 Some(v) => {
                     if v != ver.name {
@@ -217,8 +500,16 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
                     }
                 }
             };
+            if pinned == ver.name {
+                note = (note + " (pinned)").trim().to_string();
+            }
             let als = ver.aliases.join(", ");
-            tab.add_row(row!(dflt, ver.name, note, als));
+            if showroot {
+                let root = ver.root.unwrap_or("".to_string());
+                tab.add_row(row!(dflt, ver.name, note, als, root));
+            } else {
+                tab.add_row(row!(dflt, ver.name, note, als));
+            }
         }
 
         print!("{}", tab);
@@ -229,6 +520,11 @@ fn sc_list(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error
 
 // ------------------------------------------------------------------------
 
+#[derive(Serialize)]
+struct DefaultEntry {
+    name: String,
+}
+
 fn sc_default(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
     if args.is_present("version") {
         let ver = args
@@ -238,10 +534,9 @@ fn sc_default(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Er
         sc_set_default(&ver)
     } else {
         let default = sc_get_default_or_fail()?;
-        if args.is_present("json") || mainargs.is_present("json") {
-            println!("{{");
-            println!("  \"name\": \"{}\"", default);
-            println!("}}");
+        let format = get_output_format(&[args, mainargs]);
+        if format != OutputFormat::Text {
+            print_output(format, &DefaultEntry { name: default })?;
         } else {
             println!("{}", default);
         }
@@ -270,9 +565,8 @@ pub fn sc_system_add_pak(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let devel = args.is_present("devel");
     let all = args.is_present("all");
     let vers = args.values_of("version");
-    let mut pakver = args
-        .value_of("pak-version")
-        .ok_or(SimpleError::new("Internal argument error"))?;
+    let pakver = get_pak_version(args)?;
+    let mut pakver = pakver.as_str();
     let pakverx = args.occurrences_of("pak-version") > 0;
 
     // --devel is deprecated
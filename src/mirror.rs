@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ArgMatches;
+use simple_error::*;
+use simplelog::*;
+
+use crate::config::*;
+use crate::download::*;
+use crate::resolve::*;
+
+// A mirror is a local directory or an HTTP(S) server that has the same
+// layout as the original download servers: `https://host/path/file` is
+// `<mirror>/host/path/file` in the mirror. If a mirror is set, all
+// downloads go to the mirror, so rig can work without internet access.
+
+// `rig mirror sync` needs to download from the original URLs.
+static MIRROR_BYPASS: AtomicBool = AtomicBool::new(false);
+
+pub fn get_mirror() -> Result<Option<String>, Box<dyn Error>> {
+    if MIRROR_BYPASS.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+    get_global_config_env("mirror")
+}
+
+pub fn is_remote_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn url_path(url: &str) -> &str {
+    match url.find("://") {
+        Some(idx) => &url[(idx + 3)..],
+        None => url,
+    }
+}
+
+fn mirror_path(mirror: &Path, url: &str) -> PathBuf {
+    let mut path = mirror.to_path_buf();
+    for comp in url_path(url).split('/').filter(|x| !x.is_empty()) {
+        path.push(comp);
+    }
+    path
+}
+
+pub fn mirror_url(url: &str) -> Result<String, Box<dyn Error>> {
+    let mirror = match get_mirror()? {
+        Some(x) => x,
+        None => return Ok(url.to_string()),
+    };
+
+    let murl = if is_remote_url(&mirror) {
+        mirror.trim_end_matches('/').to_string() + "/" + url_path(url)
+    } else {
+        mirror_path(Path::new(&mirror), url).display().to_string()
+    };
+    debug!("Using mirror for {}: {}", url, murl);
+
+    Ok(murl)
+}
+
+// ------------------------------------------------------------------------
+
+pub fn sc_mirror(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        Some(("sync", s)) => sc_mirror_sync(s),
+        _ => Ok(()), // unreachable
+    }
+}
+
+fn sc_mirror_sync(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let dir = match args.value_of("dir") {
+        Some(x) => x.to_string(),
+        None => match get_mirror()? {
+            Some(x) if !is_remote_url(&x) => x,
+            _ => bail!("No local mirror directory is configured, use `--dir`"),
+        },
+    };
+    let dir = PathBuf::from(dir);
+
+    let vers: Vec<String> = match args.values_of("version") {
+        Some(x) => x.map(|v| v.to_string()).collect(),
+        None => vec!["release".to_string()],
+    };
+    let platforms: Vec<String> = match args.values_of("platform") {
+        Some(x) => x.map(|v| v.to_string()).collect(),
+        None => vec![default_platform()?],
+    };
+
+    MIRROR_BYPASS.store(true, Ordering::Relaxed);
+    let client = new_client()?;
+
+    info!("Updating R version metadata in {}", dir.display());
+    for url in resolve_metadata_urls()? {
+        let target = mirror_path(&dir, &url);
+        debug!("Downloading {} -> {}", url, target.display());
+        download_file(&client, &url, target.as_os_str())?;
+    }
+
+    for platform in platforms {
+        let (os, arch, linux) = parse_platform(&platform)?;
+        let rvers = resolve_versions(vers.to_owned(), os, arch, linux)?;
+        for (spec, rver) in vers.iter().zip(rvers.iter()) {
+            let version = rver.version.to_owned().unwrap_or("???".to_string());
+            let url = match &rver.url {
+                Some(x) => x,
+                None => {
+                    warn!("No installer for R {} on {}, skipping", version, platform);
+                    continue;
+                }
+            };
+            let target = mirror_path(&dir, url);
+            // devel and next builds change, so always update them
+            let snapshot = spec == "devel" || spec == "next";
+            if target.exists() && !snapshot {
+                info!("R {} for {} is already in the mirror", version, platform);
+                continue;
+            }
+            info!("Downloading {} -> {}", url, target.display());
+            download_file(&client, url, target.as_os_str())?;
+            let sidecar = url.to_string() + ".sha256";
+            let sidecar_target = mirror_path(&dir, &sidecar);
+            if let Err(err) = download_file(&client, &sidecar, sidecar_target.as_os_str()) {
+                debug!("No checksum file for {}: {}", url, err.to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
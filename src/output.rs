@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::ArgMatches;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+// Structured output of the commands that print data. `--json` (on the
+// command, or on a parent command) is the same as `--format json`. Every
+// command prints a list of records, or a single record, and the record
+// fields are the same in all formats, so they form the stable schema of
+// the output. In TSV output list fields are comma separated, and tabs,
+// newlines and backslashes are escaped with a backslash.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Yaml,
+    Tsv,
+}
+
+pub fn get_output_format(args: &[&ArgMatches]) -> OutputFormat {
+    for arg in args {
+        match arg.value_of("format") {
+            Some("json") => return OutputFormat::Json,
+            Some("yaml") => return OutputFormat::Yaml,
+            Some("tsv") => return OutputFormat::Tsv,
+            _ => {}
+        };
+    }
+    if args.iter().any(|x| x.is_present("json")) {
+        OutputFormat::Json
+    } else {
+        OutputFormat::Text
+    }
+}
+
+pub fn print_output<T: Serialize>(format: OutputFormat, data: &T) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(data)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(data)?),
+        OutputFormat::Tsv => print!("{}", to_tsv(&serde_json::to_value(data)?)),
+        OutputFormat::Text => {} // the commands print text themselves
+    };
+    Ok(())
+}
+
+fn to_tsv(data: &Value) -> String {
+    let rows: Vec<&Value> = match data {
+        Value::Array(x) => x.iter().collect(),
+        x => vec![x],
+    };
+    let header: Vec<String> = match rows.first() {
+        Some(Value::Object(x)) => x.keys().map(|x| x.to_string()).collect(),
+        Some(_) => vec!["value".to_string()],
+        None => return "".to_string(),
+    };
+
+    let mut out = header.join("\t") + "\n";
+    for row in rows {
+        let cells: Vec<String> = match row {
+            Value::Object(x) => header
+                .iter()
+                .map(|k| tsv_cell(x.get(k).unwrap_or(&Value::Null)))
+                .collect(),
+            x => vec![tsv_cell(x)],
+        };
+        out = out + &cells.join("\t") + "\n";
+    }
+    out
+}
+
+fn tsv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(x) => tsv_escape(x),
+        Value::Array(x) => x.iter().map(tsv_cell).collect::<Vec<String>>().join(","),
+        Value::Object(_) => tsv_escape(&value.to_string()),
+        x => x.to_string(),
+    }
+}
+
+fn tsv_escape(x: &str) -> String {
+    x.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+// Paths use forward slashes on Windows as well, like before. On Unix a
+// backslash is a valid file name character, so it is kept.
+pub fn serialize_path<S: Serializer>(path: &Path, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&slashes(&path.display().to_string()))
+}
+
+pub fn serialize_path_opt<S: Serializer>(path: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(x) => s.serialize_str(&slashes(x)),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn slashes(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+#[cfg(not(target_os = "windows"))]
+fn slashes(path: &str) -> String {
+    path.to_string()
+}
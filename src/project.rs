@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::Serialize;
+use simple_error::*;
+use simplelog::*;
+
+use crate::common::*;
+use crate::error::*;
+use crate::output::*;
+use crate::resolve::*;
+use crate::rversion::*;
+use crate::utils::*;
+
+// A project can pin an R version with a `.rig-version` or `.Rversion`
+// file. The file has a single version specification, e.g. `4.2`,
+// `4.1.3` or `release`. rig looks for it in the current directory and
+// its parents.
+
+const PIN_FILES: [&str; 2] = [".rig-version", ".Rversion"];
+
+lazy_static! {
+    static ref RE_MINOR: Regex = Regex::new(r"^[0-9]+[.][0-9]+$").unwrap();
+    static ref RE_VERSION: Regex = Regex::new(r"^[0-9]+[.][0-9]+[.][0-9]+$").unwrap();
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectPin {
+    pub spec: String,
+    pub file: PathBuf,
+}
+
+pub fn find_project_pin() -> Result<Option<ProjectPin>, Box<dyn Error>> {
+    let cwd = std::env::current_dir()?;
+    let mut dir: Option<&Path> = Some(&cwd);
+    while let Some(d) = dir {
+        for name in PIN_FILES {
+            let file = d.join(name);
+            if file.is_file() {
+                let spec = parse_pin_file(&file)?;
+                debug!("Found R version pin {} in {}", spec, file.display());
+                return Ok(Some(ProjectPin { spec, file }));
+            }
+        }
+        dir = d.parent();
+    }
+    Ok(None)
+}
+
+fn parse_pin_file(file: &Path) -> Result<String, Box<dyn Error>> {
+    let lines = read_lines(file)?;
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        return Ok(line.to_string());
+    }
+    bail!("No R version in {}", file.display());
+}
+
+// Match a pin against the installed R versions, without resolving
+// symbolic versions.
+
+fn match_installed(spec: &str, vers: &[InstalledVersion]) -> Option<InstalledVersion> {
+    if let Some(v) = vers
+        .iter()
+        .find(|v| v.name == spec || v.aliases.iter().any(|a| a == spec))
+    {
+        return Some(v.to_owned());
+    }
+
+    let mut matching: Vec<(semver::Version, &InstalledVersion)> = vers
+        .iter()
+        .filter_map(|v| {
+            let ver = v.version.as_ref()?;
+            let sv = semver::Version::parse(ver).ok()?;
+            let ok = if RE_MINOR.is_match(spec) {
+                ver.starts_with(&(spec.to_string() + "."))
+            } else {
+                RE_VERSION.is_match(spec) && ver == spec
+            };
+            if ok {
+                Some((sv, v))
+            } else {
+                None
+            }
+        })
+        .collect();
+    matching.sort_by(|a, b| a.0.cmp(&b.0));
+    matching.last().map(|x| x.1.to_owned())
+}
+
+pub fn match_pin_installed(spec: &str) -> Result<Option<InstalledVersion>, Box<dyn Error>> {
+    let vers = sc_get_list_details()?;
+    Ok(match_installed(spec, &vers))
+}
+
+// The installed R version for an explicit version argument, e.g. in
+// `rig which 4.2` or `rig run -r 4.2`, matched like a pin.
+
+pub fn match_version_installed(spec: &str) -> Result<String, Box<dyn Error>> {
+    match match_pin_installed(spec)? {
+        Some(v) => Ok(v.name),
+        None => rig_bail!(NotInstalled, "R version <b>{}</b> is not installed", spec),
+    }
+}
+
+// Like match_pin_installed(), but symbolic versions, e.g. `release`, that
+// are not aliases, are resolved to a version number first.
+
+pub fn match_pin(pin: &ProjectPin) -> Result<InstalledVersion, Box<dyn Error>> {
+    let vers = sc_get_list_details()?;
+    if let Some(v) = match_spec_installed(&pin.spec, &vers)? {
+        return Ok(v);
+    }
+
+    rig_bail!(
+        NotInstalled,
+        "R {} is required by {}, but it is not installed, \
+         run `rig add --from-project` to install it",
+        pin.spec,
+        pin.file.display()
+    );
+}
+
+pub fn match_spec_installed(
+    spec: &str,
+    vers: &[InstalledVersion],
+) -> Result<Option<InstalledVersion>, Box<dyn Error>> {
+    if let Some(v) = match_installed(spec, vers) {
+        return Ok(Some(v));
+    }
+
+    if !RE_MINOR.is_match(spec) && !RE_VERSION.is_match(spec) {
+        let (os, arch, linux) = parse_platform(&default_platform()?)?;
+        let rver = resolve_versions(vec![spec.to_owned()], os, arch, linux)?;
+        if let Some(num) = &rver[0].version {
+            return Ok(match_installed(num, vers));
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn get_project_pin_or_fail() -> Result<ProjectPin, Box<dyn Error>> {
+    match find_project_pin()? {
+        Some(pin) => Ok(pin),
+        None => bail!(
+            "No {} file in the current directory or its parents",
+            PIN_FILES.join(" or ")
+        ),
+    }
+}
+
+// -- rig which -----------------------------------------------------------
+
+#[derive(Serialize)]
+struct WhichEntry {
+    name: String,
+    #[serde(serialize_with = "crate::output::serialize_path_opt")]
+    binary: Option<String>,
+    #[serde(serialize_with = "crate::output::serialize_path_opt")]
+    pin: Option<String>,
+}
+
+pub fn sc_which(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let (ver, pin) = match args.value_of("version") {
+        Some(x) => (match_version_installed(x)?, None),
+        None => match find_project_pin()? {
+            Some(pin) => (match_pin(&pin)?.name, Some(pin)),
+            None => (sc_get_default_or_fail()?, None),
+        },
+    };
+
+    let inst = sc_get_list_details()?;
+    let inst = match inst.iter().find(|x| x.name == ver) {
+        Some(x) => x,
+        None => rig_bail!(NotInstalled, "R version <b>{}</b> is not installed", ver),
+    };
+    let binary = match &inst.binary {
+        Some(x) => x.to_string(),
+        None => bail!("Cannot find R binary for R {}", ver),
+    };
+
+    let format = get_output_format(&[args, mainargs]);
+    if format != OutputFormat::Text {
+        let out = WhichEntry {
+            name: inst.name.to_string(),
+            binary: Some(binary.to_string()),
+            pin: pin.map(|p| p.file.display().to_string()),
+        };
+        print_output(format, &out)?;
+    } else {
+        println!("{}", binary);
+    }
+
+    Ok(())
+}
@@ -39,7 +39,8 @@ fn filter_ok_versions(all: Vec<InstalledVersion>)
                 version: Some(v),
                 path: Some(p),
                 binary: Some(b),
-                aliases: _
+                aliases: _,
+                root: _
             } => {
                 if let Ok(sv) = semver::Version::parse(v) {
                     ok.push(OKInstalledVersion {
@@ -0,0 +1,334 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use regex::Regex;
+use serde_derive::Serialize;
+use simple_error::*;
+use simplelog::*;
+use tabular::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::common::*;
+use crate::error::*;
+use crate::escalate::*;
+use crate::output::*;
+use crate::utils::*;
+
+// The repository settings of an R installation live in its system
+// profile, each in its own `## rig <name> start` ... `## rig <name> end`
+// section, like the user library setup. Setting a value replaces its
+// section, so doing it again does not change the profile. Older rig
+// versions appended these settings without markers, rig removes those
+// lines when it updates the same setting.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepoSetting {
+    Cran,
+    Rspm,
+    Sysreqs,
+}
+
+pub const REPO_SETTINGS: [RepoSetting; 3] = [RepoSetting::Cran, RepoSetting::Rspm, RepoSetting::Sysreqs];
+
+impl RepoSetting {
+    // Name of the profile section
+    pub fn block(&self) -> &'static str {
+        match self {
+            RepoSetting::Cran => "CRAN",
+            RepoSetting::Rspm => "RSPM",
+            RepoSetting::Sysreqs => "PKG_SYSREQS",
+        }
+    }
+
+    // Name of the command line option
+    fn option(&self) -> &'static str {
+        match self {
+            RepoSetting::Cran => "cran",
+            RepoSetting::Rspm => "rspm",
+            RepoSetting::Sysreqs => "sysreqs",
+        }
+    }
+
+    // The CRAN and RSPM code work in any order, they only update their
+    // own entry in the `repos` option.
+    fn code(&self, value: &str) -> Vec<String> {
+        match self {
+            RepoSetting::Cran => vec![format!(
+                r#"local({{ r <- getOption("repos"); r["CRAN"] <- "{}"; options(repos = r) }})"#,
+                value
+            )],
+            RepoSetting::Rspm => vec![
+                format!(
+                    r#"local({{ r <- getOption("repos"); options(repos = c(RSPM = "{}", r[names(r) != "RSPM"])) }})"#,
+                    value
+                ),
+                r#"options(HTTPUserAgent = sprintf("R/%s R (%s)", getRversion(), paste(getRversion(), R.version$platform, R.version$arch, R.version$os)))"#.to_string(),
+            ],
+            RepoSetting::Sysreqs => vec![format!(r#"Sys.setenv(PKG_SYSREQS = "{}")"#, value)],
+        }
+    }
+
+    // Lines that older rig versions appended
+    fn legacy(&self) -> Vec<&'static str> {
+        match self {
+            RepoSetting::Cran => vec![r#"^options\(repos = c\(CRAN = ""#],
+            RepoSetting::Rspm => vec![
+                r#"^options\(repos = c\(RSPM=""#,
+                r#"^options\(HTTPUserAgent = sprintf\("R/%s R \(%s\)""#,
+            ],
+            RepoSetting::Sysreqs => vec![r#"^Sys\.setenv\(PKG_SYSREQS = "true"\)"#],
+        }
+    }
+
+    // Matches the value in the new and in the legacy code
+    fn value_regex(&self) -> Result<Regex, Box<dyn Error>> {
+        let re = match self {
+            RepoSetting::Cran => r#"CRAN"?\]? *(?:=|<-) *"([^"]*)""#,
+            RepoSetting::Rspm => r#"RSPM"?\]? *(?:=|<-) *"([^"]*)""#,
+            RepoSetting::Sysreqs => r#"PKG_SYSREQS *= *"([^"]*)""#,
+        };
+        Ok(Regex::new(re)?)
+    }
+}
+
+// The `## rig <name> start` ... `## rig <name> end` sections of a
+// profile, as (first, last) line indices. Stray markers are sections of
+// a single line.
+fn find_profile_blocks(lines: &[String], name: &str) -> Result<Vec<(usize, usize)>, Box<dyn Error>> {
+    let re_start = Regex::new(&format!("^## rig {} start", name))?;
+    let re_end = Regex::new(&format!("^## rig {} end", name))?;
+    let mut blocks: Vec<(usize, usize)> = vec![];
+    let mut idx = 0;
+    while idx < lines.len() {
+        if re_start.is_match(&lines[idx]) {
+            let end = (idx + 1..lines.len())
+                .take_while(|i| !re_start.is_match(&lines[*i]))
+                .find(|i| re_end.is_match(&lines[*i]));
+            let last = end.unwrap_or(idx);
+            blocks.push((idx, last));
+            idx = last + 1;
+            continue;
+        }
+        if re_end.is_match(&lines[idx]) {
+            blocks.push((idx, idx));
+        }
+        idx += 1;
+    }
+    Ok(blocks)
+}
+
+// Replace all `name` sections (and stray markers) with a single section
+// containing `code`, at the place of the first one, or at the end. With
+// `None` the sections are removed.
+pub fn replace_profile_block(
+    lines: &[String],
+    name: &str,
+    code: Option<Vec<String>>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let blocks = find_profile_blocks(lines, name)?;
+    let new: Vec<String> = match code {
+        Some(code) => {
+            let mut new = vec![format!("## rig {} start", name)];
+            new.extend(code);
+            new.push(format!("## rig {} end", name));
+            new
+        }
+        None => vec![],
+    };
+
+    let mut result: Vec<String> = vec![];
+    let mut idx = 0;
+    for (i, (first, last)) in blocks.iter().enumerate() {
+        result.extend_from_slice(&lines[idx..*first]);
+        if i == 0 {
+            result.extend_from_slice(&new);
+        }
+        idx = last + 1;
+    }
+    result.extend_from_slice(&lines[idx..]);
+    if blocks.is_empty() {
+        result.extend(new);
+    }
+
+    Ok(result)
+}
+
+// Indices of the legacy lines of a setting, outside of rig sections
+fn legacy_lines(lines: &[String], setting: RepoSetting) -> Result<Vec<usize>, Box<dyn Error>> {
+    let res: Vec<Regex> = setting
+        .legacy()
+        .iter()
+        .map(|x| Regex::new(x))
+        .collect::<Result<Vec<Regex>, regex::Error>>()?;
+    let re_marker = Regex::new(r"^## rig \S+ (start|end)")?;
+    let mut inside = false;
+    let mut result: Vec<usize> = vec![];
+    for (idx, line) in lines.iter().enumerate() {
+        if let Some(m) = re_marker.captures(line) {
+            inside = &m[1] == "start";
+        } else if !inside && res.iter().any(|re| re.is_match(line)) {
+            result.push(idx);
+        }
+    }
+    Ok(result)
+}
+
+// The value that is in effect, i.e. the last one
+pub fn get_repo_setting(lines: &[String], setting: RepoSetting) -> Result<Option<String>, Box<dyn Error>> {
+    let blocks: Vec<(usize, usize)> = find_profile_blocks(lines, setting.block())?
+        .into_iter()
+        .filter(|(first, last)| first < last)
+        .collect();
+    let code: Vec<&String> = match blocks.last() {
+        Some((first, last)) => lines[first + 1..*last].iter().collect(),
+        None => legacy_lines(lines, setting)?.iter().map(|i| &lines[*i]).collect(),
+    };
+    let re = setting.value_regex()?;
+    Ok(code
+        .iter()
+        .rev()
+        .find_map(|line| re.captures(line).map(|m| m[1].to_string())))
+}
+
+pub fn set_repo_setting(
+    profile: &Path,
+    setting: RepoSetting,
+    value: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let lines = read_lines(profile)?;
+    let legacy = legacy_lines(&lines, setting)?;
+    let current: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !legacy.contains(i))
+        .map(|(_, x)| x.to_string())
+        .collect();
+    let code = value.map(|x| setting.code(x));
+    let new = replace_profile_block(&current, setting.block(), code)?;
+    if new != lines {
+        write_lines(profile, &new)?;
+    }
+    Ok(())
+}
+
+// Problems that `rig doctor` reports: legacy lines and repeated sections
+pub fn check_repo_settings(lines: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut problems: Vec<String> = vec![];
+    for setting in REPO_SETTINGS {
+        let nlegacy = legacy_lines(lines, setting)?.len();
+        if nlegacy > 0 {
+            problems.push(format!("{} line(s) of {} settings without rig markers", nlegacy, setting.block()));
+        }
+        let nblocks = find_profile_blocks(lines, setting.block())?.len();
+        if nblocks > 1 {
+            problems.push(format!("{} {} sections or markers", nblocks, setting.block()));
+        }
+    }
+    Ok(problems)
+}
+
+// Rewrite the settings that are in effect into a single section each
+pub fn tidy_repo_settings(profile: &Path) -> Result<(), Box<dyn Error>> {
+    for setting in REPO_SETTINGS {
+        let value = get_repo_setting(&read_lines(profile)?, setting)?;
+        set_repo_setting(profile, setting, value.as_deref())?;
+    }
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct ReposEntry {
+    version: String,
+    cran: Option<String>,
+    rspm: Option<String>,
+    sysreqs: Option<bool>,
+}
+
+pub fn sc_system_repos(args: &ArgMatches, mainargs: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        Some(("show", s)) => sc_system_repos_show(s, args, mainargs),
+        Some(("set", s)) => sc_system_repos_set(s),
+        Some(("unset", s)) => sc_system_repos_unset(s),
+        _ => Ok(()), // unreachable
+    }
+}
+
+fn repos_profile(args: &ArgMatches) -> Result<(String, PathBuf), Box<dyn Error>> {
+    let ver = require_with!(args.value_of("version"), "clap error").to_string();
+    let ver = check_installed(&ver)?;
+    let profile = get_system_profile(&ver)?;
+    if !profile.exists() {
+        bail!("Cannot find system profile of R {} at {}", ver, profile.display());
+    }
+    Ok((ver, profile))
+}
+
+fn sc_system_repos_show(
+    args: &ArgMatches,
+    reposargs: &ArgMatches,
+    mainargs: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let (ver, profile) = repos_profile(args)?;
+    let lines = read_lines(&profile)?;
+    let entry = ReposEntry {
+        version: ver,
+        cran: get_repo_setting(&lines, RepoSetting::Cran)?,
+        rspm: get_repo_setting(&lines, RepoSetting::Rspm)?,
+        sysreqs: get_repo_setting(&lines, RepoSetting::Sysreqs)?.map(|x| x == "true"),
+    };
+
+    let format = get_output_format(&[args, reposargs, mainargs]);
+    if format != OutputFormat::Text {
+        return print_output(format, &entry);
+    }
+
+    let notset = "(not set)".to_string();
+    let mut tab = Table::new("{:<}  {:<}");
+    tab.add_row(row!("CRAN", entry.cran.unwrap_or_else(|| notset.to_string())));
+    tab.add_row(row!("RSPM", entry.rspm.unwrap_or_else(|| notset.to_string())));
+    tab.add_row(row!(
+        "sysreqs",
+        entry.sysreqs.map(|x| x.to_string()).unwrap_or(notset)
+    ));
+    print!("{}", tab);
+
+    Ok(())
+}
+
+fn sc_system_repos_set(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    escalate("updating repository settings")?;
+    let (ver, profile) = repos_profile(args)?;
+    for setting in REPO_SETTINGS {
+        if let Some(value) = args.value_of(setting.option()) {
+            if value.is_empty() || value.contains(['"', '\\', '\n']) {
+                rig_bail!(InvalidSpec, "Invalid {} URL: {}", setting.block(), value);
+            }
+            info!("Setting {} of R {} to {}", setting.block(), ver, value);
+            set_repo_setting(&profile, setting, Some(value))?;
+        }
+    }
+    Ok(())
+}
+
+fn sc_system_repos_unset(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    escalate("updating repository settings")?;
+    let (ver, profile) = repos_profile(args)?;
+    for setting in REPO_SETTINGS {
+        if args.is_present(setting.option()) {
+            info!("Removing {} setting of R {}", setting.block(), ver);
+            set_repo_setting(&profile, setting, None)?;
+        }
+    }
+    Ok(())
+}
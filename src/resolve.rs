@@ -6,29 +6,47 @@ use regex::Regex;
 use semver::Version;
 use simple_error::{bail, SimpleError};
 
+use crate::config::*;
 use crate::download::*;
+use crate::error::*;
 use crate::rversion::*;
 use crate::utils::*;
 
-const API_URI: &str = "https://api.r-hub.io/rversions/";
+#[cfg(target_os = "linux")]
+use crate::distros::*;
+#[cfg(target_os = "linux")]
+use crate::linux::detect_linux;
 
-const MACOS_DEVEL_URI: &str =
-    "https://mac.R-project.org/high-sierra/last-success/R-devel-x86_64.pkg";
-const MACOS_DEVEL_ARM_URI: &str =
-    "https://mac.r-project.org/big-sur/last-success/R-devel-arm64.pkg";
+// URL templates refer to the base URLs as `{api}`, `{cran}`, `{cdn}`,
+// etc. The base URLs and their defaults are config keys, see
+// `BASE_URLS`, so they can be changed in the config file, or with the
+// `RIG_API_URL`, `RIG_CRAN_URL`, etc. env vars.
+const BASE_URLS: [(&str, &str); 6] = [
+    ("{api}", "api-url"),
+    ("{cran}", "cran-url"),
+    ("{cran-archive}", "cran-archive-url"),
+    ("{cdn}", "cdn-url"),
+    ("{mac}", "mac-url"),
+    ("{svn}", "svn-url"),
+];
 
-const MACOS_325_URI: &str = "https://cloud.r-project.org/bin/macosx/old/R-3.2.4-revised.pkg";
-const MACOS_OLD2_URI: &str = "https://cloud.r-project.org/bin/macosx/old/R-{}.pkg";
-const MACOS_OLD_URI: &str = "https://cloud.r-project.org/bin/macosx/R-{}.pkg";
-const MACOS_URI: &str = "https://cloud.r-project.org/bin/macosx/base/R-{}.pkg";
+const API_URI: &str = "{api}/";
+
+const MACOS_DEVEL_URI: &str = "{mac}/high-sierra/last-success/R-devel-x86_64.pkg";
+const MACOS_DEVEL_ARM_URI: &str = "{mac}/big-sur/last-success/R-devel-arm64.pkg";
+
+const MACOS_325_URI: &str = "{cran}/bin/macosx/old/R-3.2.4-revised.pkg";
+const MACOS_OLD2_URI: &str = "{cran}/bin/macosx/old/R-{}.pkg";
+const MACOS_OLD_URI: &str = "{cran}/bin/macosx/R-{}.pkg";
+const MACOS_URI: &str = "{cran}/bin/macosx/base/R-{}.pkg";
 const MACOS_ARM_URI: &str =
-    "https://cloud.r-project.org/bin/macosx/big-sur-arm64/base/R-{}-arm64.pkg";
+    "{cran}/bin/macosx/big-sur-arm64/base/R-{}-arm64.pkg";
 
-const WIN_DEVEL_URI: &str = "https://cloud.r-project.org/bin/windows/base/R-devel-win.exe";
-const WIN_URI: &str = "https://cloud.r-project.org/bin/windows/base/old/{}/R-{}-win.exe";
-const WIN_OLD: &str = "https://cran-archive.r-project.org/bin/windows/base/old/{}/R-{}-win.exe";
+const WIN_DEVEL_URI: &str = "{cran}/bin/windows/base/R-devel-win.exe";
+const WIN_URI: &str = "{cran}/bin/windows/base/old/{}/R-{}-win.exe";
+const WIN_OLD: &str = "{cran-archive}/bin/windows/base/old/{}/R-{}-win.exe";
 
-const DEVEL_VERSION_URI: &str = "https://svn.r-project.org/R/trunk/VERSION";
+const DEVEL_VERSION_URI: &str = "{svn}/R/trunk/VERSION";
 
 lazy_static! {
     static ref RE_OLDREL: Regex = Regex::new(r"^oldrel/[0-9]+$").unwrap();
@@ -43,7 +61,7 @@ pub async fn resolve_versions(
     arch: String,
     linux: Option<LinuxVersion>,
 ) -> Result<Vec<Rversion>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
+    let client = new_client()?;
     let client = &client;
     let os = &os;
     let arch = &arch;
@@ -67,7 +85,7 @@ pub async fn resolve_versions(
                 } else if RE_VERSION.is_match(&ver) {
                     resolve_version(client, &ver, os, arch, linux).await
                 } else {
-                    bail!("Unknown version specification: {}", ver);
+                    rig_bail!(InvalidSpec, "Unknown version specification: {}", ver);
                 }
             }
         }))
@@ -78,7 +96,7 @@ pub async fn resolve_versions(
     for o in out {
         match o {
             Ok(x) => out2.push(x),
-            Err(x) => bail!("Failed to resolve R version: {}", x.to_string()),
+            Err(x) => return Err(error_context(x, "Failed to resolve R version")),
         };
     }
 
@@ -93,24 +111,24 @@ async fn resolve_release(
 ) -> Result<Rversion, Box<dyn Error>> {
     let url;
     if os == "macos" {
-        url = API_URI.to_string() + "r-release-macos";
+        url = expand_url(API_URI)? + "r-release-macos";
     } else if os == "win" {
-        url = API_URI.to_string() + "r-release-win";
+        url = expand_url(API_URI)? + "r-release-win";
     } else if os == "linux" {
-        url = API_URI.to_string() + "r-release";
+        url = expand_url(API_URI)? + "r-release";
     } else {
-        bail!("Unknown OS: {}", os);
+        rig_bail!(UnsupportedPlatform, "Unknown OS: {}", os);
     }
 
-    let v = download_json(client, vec![url]).await?;
-    let v = &v[0]["version"];
-    let v = match v {
+    let resp = download_json(client, vec![url]).await?;
+    let v = match &resp[0]["version"] {
         serde_json::Value::String(s) => s,
         _ => bail!("Failed to parse response from rversions API"),
     };
     let dlurl = get_download_url(&v, os, arch, linux)?;
     Ok(Rversion {
         version: Some(v.to_string()),
+        sha256: api_sha256(&resp[0], &dlurl),
         url: dlurl,
         arch: Some(arch.to_string()),
     })
@@ -122,7 +140,7 @@ async fn resolve_devel(
     arch: &String,
     linux: Option<LinuxVersion>,
 ) -> Result<Rversion, Box<dyn Error>> {
-    let url = DEVEL_VERSION_URI.to_string();
+    let url = expand_url(DEVEL_VERSION_URI)?;
     let txt = download_text(client, url).await?;
     let ver = txt
         .split(" ")
@@ -133,26 +151,29 @@ async fn resolve_devel(
         if arch == "x86_64" {
             Ok(Rversion {
                 version: Some(ver),
-                url: Some(MACOS_DEVEL_URI.to_string()),
+                url: Some(expand_url(MACOS_DEVEL_URI)?),
                 arch: Some(arch.to_string()),
+                sha256: None,
             })
         } else {
             Ok(Rversion {
                 version: Some(ver),
-                url: Some(MACOS_DEVEL_ARM_URI.to_string()),
+                url: Some(expand_url(MACOS_DEVEL_ARM_URI)?),
                 arch: Some(arch.to_string()),
+                sha256: None,
             })
         }
     } else if os == "win" {
         Ok(Rversion {
             version: Some(ver),
-            url: Some(WIN_DEVEL_URI.to_string()),
+            url: Some(expand_url(WIN_DEVEL_URI)?),
             arch: Some(arch.to_string()),
+            sha256: None,
         })
     } else if os == "linux" {
         fn rep(tmpl: &str, sub: &str) -> Result<String, Box<dyn Error>> {
             let re = Regex::new("[{][}]")?;
-            Ok(re.replace_all(tmpl, sub).to_string())
+            Ok(re.replace_all(&expand_url(tmpl)?, sub).to_string())
         }
         let linux = linux.ok_or(SimpleError::new("Internal error, no Linux distro"))?;
         let url = rep(&linux.url, "devel")?;
@@ -160,9 +181,10 @@ async fn resolve_devel(
             version: Some(ver),
             url: Some(url),
             arch: None,
+            sha256: None,
         })
     } else {
-        bail!("Unknown OS: {}", os);
+        rig_bail!(UnsupportedPlatform, "Unknown OS: {}", os);
     }
 }
 
@@ -180,10 +202,10 @@ async fn resolve_next(
     } else if os == "linux" {
         ep = "/r-next".to_string();
     } else {
-        bail!("Unknown OS:{}", os);
+        rig_bail!(UnsupportedPlatform, "Unknown OS: {}", os);
     }
 
-    let url = API_URI.to_string() + &ep;
+    let url = expand_url(API_URI)? + &ep;
     let resp = download_json(client, vec![url]).await?;
     let resp = &resp[0];
 
@@ -193,7 +215,7 @@ async fn resolve_next(
     if os == "linux" {
         fn rep(tmpl: &str, sub: &str) -> Result<String, Box<dyn Error>> {
             let re = Regex::new("[{][}]")?;
-            Ok(re.replace_all(tmpl, sub).to_string())
+            Ok(re.replace_all(&expand_url(tmpl)?, sub).to_string())
         }
         let linux = linux.ok_or(SimpleError::new("Internal error, no Linux distro"))?;
         url = Some(rep(&linux.url, "next")?);
@@ -203,6 +225,7 @@ async fn resolve_next(
 
     Ok(Rversion {
         version: Some(version),
+        sha256: api_sha256(resp, &url),
         url: url,
         arch: Some(arch.to_string()),
     })
@@ -215,7 +238,7 @@ async fn resolve_oldrel(
     arch: &String,
     linux: Option<LinuxVersion>,
 ) -> Result<Rversion, Box<dyn Error>> {
-    let url = API_URI.to_string() + "r-" + ver;
+    let url = expand_url(API_URI)? + "r-" + ver;
     let resp = download_json(client, vec![url]).await?;
     let version = &resp[0]["version"];
     let version = match version {
@@ -226,6 +249,7 @@ async fn resolve_oldrel(
     let dlurl = get_download_url(version, os, arch, linux)?;
     Ok(Rversion {
         version: Some(version.to_string()),
+        sha256: api_sha256(&resp[0], &dlurl),
         url: dlurl,
         arch: Some(arch.to_string()),
     })
@@ -257,7 +281,7 @@ async fn resolve_minor(
     }
 
     if !ok {
-        bail!("Cannot resolve minor R version {}", ver);
+        rig_bail!(InvalidSpec, "Cannot resolve minor R version {}", ver);
     }
 
     let dlurl = get_download_url(&out, os, arch, linux)?;
@@ -265,6 +289,7 @@ async fn resolve_minor(
         version: Some(out),
         url: dlurl,
         arch: Some(arch.to_string()),
+        sha256: None,
     })
 }
 
@@ -284,9 +309,19 @@ async fn resolve_version(
         version: Some(ver.to_string()),
         url: dlurl,
         arch: Some(arch.to_string()),
+        sha256: None,
     })
 }
 
+// The rversions API might include the SHA-256 digest of the installer
+// at `URL`. We can only use it if we download the same file.
+fn api_sha256(resp: &serde_json::Value, url: &Option<String>) -> Option<String> {
+    match (resp["URL"].as_str(), resp["sha256"].as_str(), url) {
+        (Some(apiurl), Some(sha256), Some(url)) if apiurl == url => Some(sha256.to_lowercase()),
+        _ => None,
+    }
+}
+
 fn get_download_url(
     ver: &String,
     os: &String,
@@ -295,7 +330,7 @@ fn get_download_url(
 ) -> Result<Option<String>, Box<dyn Error>> {
     fn rep(tmpl: &str, sub: &str) -> Result<String, Box<dyn Error>> {
         let re = Regex::new("[{][}]")?;
-        Ok(re.replace_all(tmpl, sub).to_string())
+        Ok(re.replace_all(&expand_url(tmpl)?, sub).to_string())
     }
 
     let vv = Version::parse(ver)?;
@@ -305,7 +340,7 @@ fn get_download_url(
             let v340 = Version::parse("3.4.0")?;
             let v400 = Version::parse("4.0.0")?;
             if ver == "3.2.5" {
-                Ok(Some(MACOS_325_URI.to_string()))
+                Ok(Some(expand_url(MACOS_325_URI)?))
             } else if vv < v2100 {
                 Ok(None)
             } else if vv < v340 {
@@ -323,7 +358,7 @@ fn get_download_url(
                 Ok(Some(rep(MACOS_ARM_URI, ver)?))
             }
         } else {
-            bail!("Unknown macOS arch: {}", arch);
+            rig_bail!(UnsupportedPlatform, "Unknown macOS arch: {}", arch);
         }
     } else if os == "win" {
         let v340 = Version::parse("3.4.0")?;
@@ -336,12 +371,12 @@ fn get_download_url(
         let linux = linux.ok_or(SimpleError::new("Internal error, no Linux distro"))?;
         Ok(Some(rep(&linux.url, ver)?))
     } else {
-        bail!("Unknown OS: {}", os);
+        rig_bail!(UnsupportedPlatform, "Unknown OS: {}", os);
     }
 }
 
 async fn download_r_versions(client: &reqwest::Client) -> Result<Vec<String>, Box<dyn Error>> {
-    let url = API_URI.to_string() + "r-versions";
+    let url = expand_url(API_URI)? + "r-versions";
     let resp = download_json(client, vec![url]).await?;
     let resp = &resp[0];
     let resp = match resp {
@@ -360,3 +395,96 @@ async fn download_r_versions(client: &reqwest::Client) -> Result<Vec<String>, Bo
 
     Ok(vers)
 }
+
+// All the metadata URLs that are needed to resolve R versions, for
+// all platforms. `rig mirror sync` downloads these.
+
+pub fn resolve_metadata_urls() -> Result<Vec<String>, Box<dyn Error>> {
+    let eps = [
+        "r-versions",
+        "r-release",
+        "r-release-macos",
+        "r-release-win",
+        "r-oldrel/1",
+        "r-oldrel/2",
+        "r-oldrel/3",
+        "r-oldrel/4",
+        "/r-next",
+        "/r-next-win",
+        "/r-next-macos-x86_64",
+        "/r-next-macos-arm64",
+    ];
+    let api = expand_url(API_URI)?;
+    let mut urls: Vec<String> = eps.iter().map(|x| api.to_owned() + x).collect();
+    urls.push(expand_url(DEVEL_VERSION_URI)?);
+    Ok(urls)
+}
+
+fn get_base_url(key: &str) -> Result<String, Box<dyn Error>> {
+    let url = get_config_string(key)?;
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+pub fn expand_url(tmpl: &str) -> Result<String, Box<dyn Error>> {
+    let mut url = tmpl.to_string();
+    for (pattern, key) in BASE_URLS {
+        if url.contains(pattern) {
+            url = url.replace(pattern, &get_base_url(key)?);
+        }
+    }
+    Ok(url)
+}
+
+// ------------------------------------------------------------------------
+// Platforms
+
+pub fn parse_platform(platform: &str) -> Result<(String, String, Option<LinuxVersion>), Box<dyn Error>> {
+    match platform {
+        "macos-x86_64" => Ok(("macos".to_string(), "x86_64".to_string(), None)),
+        "macos-arm64" => Ok(("macos".to_string(), "arm64".to_string(), None)),
+        "windows" => Ok(("win".to_string(), "default".to_string(), None)),
+        _ => parse_linux_platform(platform),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn parse_linux_platform(platform: &str) -> Result<(String, String, Option<LinuxVersion>), Box<dyn Error>> {
+    let name = platform.strip_prefix("linux-").unwrap_or(platform);
+    for lv in list_supported_distros()? {
+        if name == lv.distro.to_owned() + "-" + &lv.version {
+            return Ok(("linux".to_string(), "default".to_string(), Some(lv)));
+        }
+    }
+    bail!(
+        "Unknown platform: {}, see `rig system distros` for Linux distributions",
+        platform
+    );
+}
+
+#[cfg(not(target_os = "linux"))]
+fn parse_linux_platform(platform: &str) -> Result<(String, String, Option<LinuxVersion>), Box<dyn Error>> {
+    bail!(
+        "Unknown platform: {}, Linux platforms are only supported on Linux",
+        platform
+    );
+}
+
+#[cfg(target_os = "linux")]
+pub fn default_platform() -> Result<String, Box<dyn Error>> {
+    let lv = detect_linux()?;
+    Ok("linux-".to_string() + &lv.distro + "-" + &lv.version)
+}
+
+#[cfg(target_os = "macos")]
+pub fn default_platform() -> Result<String, Box<dyn Error>> {
+    if std::env::consts::ARCH == "aarch64" {
+        Ok("macos-arm64".to_string())
+    } else {
+        Ok("macos-x86_64".to_string())
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn default_platform() -> Result<String, Box<dyn Error>> {
+    Ok("windows".to_string())
+}
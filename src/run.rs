@@ -3,10 +3,13 @@ use std::error::Error;
 use std::ffi::OsString;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::path::Path;
 use regex::Regex;
 
 use simplelog::*;
 
+use crate::error::*;
+
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use crate::rversion::*;
 #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -21,17 +24,29 @@ use crate::windows::*;
 #[cfg(target_os = "linux")]
 use crate::linux::*;
 
-pub fn run(cmd: OsString, args: Vec<OsString>, _what: &str)
+pub fn run(cmd: OsString, args: Vec<OsString>, what: &str)
+       -> Result<(), Box<dyn Error>> {
+    run_in_dir(Path::new("."), cmd, args, what)
+}
+
+pub fn run_in_dir(dir: &Path, cmd: OsString, args: Vec<OsString>, what: &str)
        -> Result<(), Box<dyn Error>> {
 
-    debug!("Running {:?} with args {:?}", cmd, args);
-    let reader = duct::cmd(cmd, args)
+    debug!("Running {:?} with args {:?} in {}", cmd, args, dir.display());
+    let reader = match duct::cmd(cmd, args)
+	.dir(dir)
 	.env("DEBIAN_FRONTEND", "noninteractive")
         .stderr_to_stdout()
-        .reader()?;
+        .reader() {
+            Ok(x) => x,
+            Err(err) => rig_bail!(ExternalCommand, "Failed to run {}: {}", what, err),
+        };
     let lines = BufReader::new(reader).lines();
     for line in lines {
-        info!("<cyan>></> {}", line?);
+        match line {
+            Ok(x) => info!("<cyan>></> {}", x),
+            Err(err) => rig_bail!(ExternalCommand, "{} failed: {}", what, err),
+        };
     }
 
     Ok(())
@@ -86,7 +101,7 @@ pub fn r(version: &str, command: &str)
 fn r_sudo(version: &str, command: &str, user: &User)
           -> Result<(), Box<dyn Error>> {
 
-    let rbin = R_ROOT.to_string() + "/" + &R_BINPATH.replace("{}", version);
+    let rbin = get_r_version_root(version)?.join(R_BINPATH.replace("{}", version)).display().to_string();
     let escaped_command =
         rbin + " --vanilla -s -e \"" +
         &command.replace("\"", "\\\"").replace("$", "\\$") +
@@ -105,7 +120,7 @@ fn r_sudo(version: &str, command: &str, user: &User)
 fn r_sudo(version: &str, command: &str, user: &User)
           -> Result<(), Box<dyn Error>> {
 
-    let rbin = R_ROOT.to_string() + "/" + &R_BINPATH.replace("{}", version);
+    let rbin = get_r_version_root(version)?.join(R_BINPATH.replace("{}", version)).display().to_string();
     let username = user.user.to_string();
 
     run(
@@ -119,7 +134,7 @@ fn r_sudo(version: &str, command: &str, user: &User)
 fn r_nosudo(version: &str, command: &str)
             -> Result<(), Box<dyn Error>> {
 
-    let rbin = R_ROOT.to_string() + "/" + &R_BINPATH.replace("{}", version);
+    let rbin = get_r_version_root(version)?.join(R_BINPATH.replace("{}", version)).display().to_string();
 
     run(
         rbin.into(),
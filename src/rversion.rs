@@ -1,23 +1,29 @@
 
 use std::cmp::Ordering;
 
+use serde_derive::Serialize;
+
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 use std::ffi::OsString;
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize)]
 pub struct Rversion {
     pub version: Option<String>,
     pub url: Option<String>,
     pub arch: Option<String>,
+    pub sha256: Option<String>,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize)]
 pub struct InstalledVersion {
     pub name: String,
     pub version: Option<String>,
+    #[serde(serialize_with = "crate::output::serialize_path_opt")]
     pub path: Option<String>,
+    #[serde(serialize_with = "crate::output::serialize_path_opt")]
     pub binary: Option<String>,
     pub aliases: Vec<String>,
+    pub root: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -48,19 +54,21 @@ impl PartialEq for OKInstalledVersion {
 
 impl Eq for OKInstalledVersion { }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct LinuxVersion {
     pub distro: String,
     pub version: String,
     pub url: String,
     pub rspm: bool,
     pub rspm_url: String,
+    pub installer: String,
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct PkgLibrary {
     pub rversion: String,
     pub name: String,
+    #[serde(serialize_with = "crate::output::serialize_path")]
     pub path: std::path::PathBuf,
     pub default: bool,
 }
@@ -75,7 +83,7 @@ pub struct User {
     pub sudo: bool,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct Alias {
     pub alias: String,
     pub version: String,
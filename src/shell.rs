@@ -0,0 +1,231 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use simple_error::*;
+use simplelog::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::common::*;
+use crate::project::*;
+use crate::renv;
+
+// `rig shell-init` prints a `rig` shell function that evaluates the
+// output of `rig use --shell <shell>`, so `rig use` can change `PATH` in
+// the current shell only. The bin directory that `rig use` added to `PATH`
+// is kept in `RIG_USE_BIN`, so the next `rig use` can remove it. `R_HOME`
+// is not set, R's launcher script sets it, and an exported `R_HOME` would
+// break other R versions started from the same shell.
+
+const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+const HOOK_SH: &str = r#"rig() {
+  if [ "$1" = "use" ]; then
+    shift
+    eval "$(command rig use --shell {shell} "$@")"
+  else
+    command rig "$@"
+  fi
+}
+"#;
+
+const HOOK_FISH: &str = r#"function rig
+  if test "$argv[1]" = "use"
+    command rig use --shell fish $argv[2..-1] | source
+  else
+    command rig $argv
+  end
+end
+"#;
+
+const AUTO_BASH: &str = r#"_rig_hook() {
+  if [ "$PWD" != "$_RIG_LAST_PWD" ]; then
+    _RIG_LAST_PWD="$PWD"
+    eval "$(command rig use --shell bash --auto)"
+  fi
+}
+case ";${PROMPT_COMMAND};" in
+  *";_rig_hook;"*) ;;
+  *) PROMPT_COMMAND="_rig_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}" ;;
+esac
+"#;
+
+const AUTO_ZSH: &str = r#"_rig_hook() {
+  eval "$(command rig use --shell zsh --auto)"
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _rig_hook
+_rig_hook
+"#;
+
+const AUTO_FISH: &str = r#"function _rig_hook --on-variable PWD
+  command rig use --shell fish --auto | source
+end
+_rig_hook
+"#;
+
+pub fn sc_shell_init(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let shell = require_with!(args.value_of("shell"), "clap error");
+    let auto = args.is_present("auto");
+
+    match shell {
+        "bash" | "zsh" => {
+            print!("{}", HOOK_SH.replace("{shell}", shell));
+            if auto {
+                print!("{}", if shell == "bash" { AUTO_BASH } else { AUTO_ZSH });
+            }
+        }
+        "fish" => {
+            print!("{}", HOOK_FISH);
+            if auto {
+                print!("{}", AUTO_FISH);
+            }
+        }
+        _ => bail!("Unsupported shell: {}", shell),
+    };
+
+    Ok(())
+}
+
+// ------------------------------------------------------------------------
+
+pub fn sc_use(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let shell = match args.value_of("shell") {
+        Some(x) => x,
+        None => bail!(
+            "`rig use` needs shell integration, add \
+             `eval \"$(rig shell-init bash)\"` (or zsh, or \
+             `rig shell-init fish | source` for fish) to your shell profile"
+        ),
+    };
+    if !SHELLS.contains(&shell) {
+        bail!("Unsupported shell: {}", shell);
+    }
+
+    if args.is_present("auto") {
+        // Never fail in the cd hook, that would print an error at every
+        // prompt, a warning is enough.
+        match find_auto_version() {
+            Ok(Some(ver)) => {
+                if std::env::var("RIG_USE_VERSION").ok().as_deref() != Some(&ver) {
+                    print_use(shell, &ver, true)?;
+                }
+            }
+            Ok(None) => {
+                if std::env::var("RIG_USE_AUTO").is_ok() {
+                    print_unuse(shell);
+                }
+            }
+            Err(err) => warn!("{}", err),
+        };
+        return Ok(());
+    }
+
+    let ver = require_with!(args.value_of("version"), "clap error");
+    let ver = if ver.ends_with("renv.lock") {
+        let needver = renv::parse_r_version(PathBuf::new().join(ver))?;
+        renv::match_r_version(&needver)?.name
+    } else {
+        check_installed(&ver.to_string())?
+    };
+    print_use(shell, &ver, false)
+}
+
+// A `.rig-version` pin, or the R version in renv.lock, in the current
+// directory or its parents.
+
+fn find_auto_version() -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(pin) = find_project_pin()? {
+        return Ok(Some(match_pin(&pin)?.name));
+    }
+
+    let cwd = std::env::current_dir()?;
+    let mut dir: Option<&Path> = Some(&cwd);
+    while let Some(d) = dir {
+        let lockfile = d.join("renv.lock");
+        if lockfile.is_file() {
+            debug!("Found {}", lockfile.display());
+            let needver = renv::parse_r_version(lockfile)?;
+            return Ok(Some(renv::match_r_version(&needver)?.name));
+        }
+        dir = d.parent();
+    }
+
+    Ok(None)
+}
+
+// PATH, without the directory that the previous `rig use` added
+fn clean_path() -> Vec<String> {
+    let old = std::env::var("RIG_USE_BIN").unwrap_or_default();
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|x| x.display().to_string())
+        .filter(|x| x != &old)
+        .collect()
+}
+
+fn print_use(shell: &str, ver: &str, auto: bool) -> Result<(), Box<dyn Error>> {
+    let rbin = get_r_binary(ver)?;
+    if !rbin.exists() {
+        bail!("Cannot find R binary for R {} at {}", ver, rbin.display());
+    }
+    let bindir = match get_rscript_binary(ver)?.parent() {
+        Some(x) => x.display().to_string(),
+        None => bail!("Cannot find bin directory of R {}", ver),
+    };
+
+    let mut path = vec![bindir.to_owned()];
+    path.append(&mut clean_path());
+
+    print_set(shell, "PATH", &path);
+    print_set(shell, "RIG_USE_BIN", &[bindir]);
+    print_set(shell, "RIG_USE_VERSION", &[ver.to_string()]);
+    if auto {
+        print_set(shell, "RIG_USE_AUTO", &["1".to_string()]);
+    } else {
+        print_unset(shell, "RIG_USE_AUTO");
+    }
+    info!("Using R {} in this shell", ver);
+
+    Ok(())
+}
+
+fn print_unuse(shell: &str) {
+    print_set(shell, "PATH", &clean_path());
+    for var in ["RIG_USE_BIN", "RIG_USE_VERSION", "RIG_USE_AUTO"] {
+        print_unset(shell, var);
+    }
+}
+
+fn print_set(shell: &str, var: &str, values: &[String]) {
+    if shell == "fish" {
+        let values: Vec<String> = values.iter().map(|x| quote_fish(x)).collect();
+        println!("set -gx {} {};", var, values.join(" "));
+    } else {
+        println!("export {}={};", var, quote_sh(&values.join(":")));
+    }
+}
+
+fn print_unset(shell: &str, var: &str) {
+    if shell == "fish" {
+        println!("set -e {};", var);
+    } else {
+        println!("unset {};", var);
+    }
+}
+
+fn quote_sh(x: &str) -> String {
+    "'".to_string() + &x.replace('\'', "'\\''") + "'"
+}
+
+fn quote_fish(x: &str) -> String {
+    "'".to_string() + &x.replace('\\', "\\\\").replace('\'', "\\'") + "'"
+}
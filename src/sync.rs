@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use serde_derive::Deserialize;
+use simple_error::*;
+use simplelog::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::alias::*;
+use crate::common::*;
+use crate::error::*;
+use crate::escalate::*;
+use crate::library::*;
+use crate::project::*;
+use crate::repos::*;
+use crate::utils::*;
+
+// `rig sync` installs R versions, and sets up aliases, the default
+// version, libraries, pak and the repositories, as listed in a
+// `rig.toml` file. It first
+// creates a plan, by comparing `rig.toml` to the current installations,
+// so running it again is a no-op.
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Manifest {
+    #[serde(default)]
+    versions: Vec<String>,
+    default: Option<String>,
+    pak: Option<PakSetting>,
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    libraries: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    repos: RepoSettings,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum PakSetting {
+    Enabled(bool),
+    Stream(String),
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RepoSettings {
+    cran_mirror: Option<String>,
+    rspm: Option<bool>,
+    sysreqs: Option<bool>,
+}
+
+#[derive(Debug)]
+enum SyncAction {
+    Add(String),
+    Alias(String, String),
+    Default(String),
+    Library(String, String),
+    Pak(String, String),
+    Remove(String),
+    Repos(String, RepoSetting, Option<String>),
+}
+
+impl std::fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SyncAction::Add(spec) => write!(f, "add R {}", spec),
+            SyncAction::Alias(alias, spec) => write!(f, "add alias {} for R {}", alias, spec),
+            SyncAction::Default(spec) => write!(f, "set default to R {}", spec),
+            SyncAction::Library(spec, lib) => write!(f, "add library {} for R {}", lib, spec),
+            SyncAction::Pak(spec, stream) => write!(f, "install pak ({}) for R {}", stream, spec),
+            SyncAction::Remove(ver) => write!(f, "remove R {}", ver),
+            SyncAction::Repos(ver, setting, Some(value)) => {
+                write!(f, "set {} of R {} to {}", setting.block(), ver, value)
+            }
+            SyncAction::Repos(ver, setting, None) => {
+                write!(f, "remove {} setting of R {}", setting.block(), ver)
+            }
+        }
+    }
+}
+
+pub fn sc_sync(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = PathBuf::from(require_with!(args.value_of("file"), "clap error"));
+    let manifest = read_manifest(&path)?;
+
+    let plan = sync_plan(&manifest, args.is_present("prune"))?;
+    if plan.is_empty() {
+        info!("R installations are up to date with {}", path.display());
+        return Ok(());
+    }
+
+    if args.is_present("dry-run") {
+        for action in plan {
+            println!("{}", action);
+        }
+        return Ok(());
+    }
+
+    sync_env(&manifest);
+    for action in plan {
+        info!("Sync: {}", action);
+        sync_apply(&action)?;
+    }
+
+    Ok(())
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+    if !path.exists() {
+        bail!("Cannot find {}", path.display());
+    }
+    let contents = read_file_string(path)?;
+    let manifest: Manifest = match toml::from_str(&contents) {
+        Ok(x) => x,
+        Err(err) => bail!("Invalid {}: {}", path.display(), err),
+    };
+
+    if let Some(PakSetting::Stream(stream)) = &manifest.pak {
+        if !["stable", "rc", "devel"].contains(&stream.as_str()) {
+            bail!(
+                "Invalid pak setting in {}: {}, must be stable, rc, devel or false",
+                path.display(),
+                stream
+            );
+        }
+    }
+
+    Ok(manifest)
+}
+
+fn pak_stream(manifest: &Manifest) -> Option<String> {
+    match &manifest.pak {
+        None | Some(PakSetting::Enabled(true)) => Some("stable".to_string()),
+        Some(PakSetting::Enabled(false)) => None,
+        Some(PakSetting::Stream(x)) => Some(x.to_string()),
+    }
+}
+
+fn sync_plan(manifest: &Manifest, prune: bool) -> Result<Vec<SyncAction>, Box<dyn Error>> {
+    let vers = sc_get_list_details()?;
+    let mut plan: Vec<SyncAction> = vec![];
+    let mut keep: Vec<String> = vec![];
+    let pak = pak_stream(manifest);
+
+    for spec in &manifest.versions {
+        match match_spec_installed(spec, &vers)? {
+            None => plan.push(SyncAction::Add(spec.to_string())),
+            Some(inst) => {
+                if let Some(stream) = &pak {
+                    if !has_pak(&inst.name) {
+                        plan.push(SyncAction::Pak(spec.to_string(), stream.to_string()));
+                    }
+                }
+                plan.extend(repos_plan(&manifest.repos, &inst.name)?);
+                keep.push(inst.name);
+            }
+        };
+    }
+
+    for (alias, spec) in &manifest.aliases {
+        match match_spec_installed(spec, &vers)? {
+            Some(inst) if inst.aliases.contains(alias) => {}
+            _ => plan.push(SyncAction::Alias(alias.to_string(), spec.to_string())),
+        };
+    }
+
+    if let Some(spec) = &manifest.default {
+        let current = sc_get_default()?;
+        match match_spec_installed(spec, &vers)? {
+            Some(inst) if Some(&inst.name) == current.as_ref() => {}
+            _ => plan.push(SyncAction::Default(spec.to_string())),
+        };
+    }
+
+    for (spec, libs) in &manifest.libraries {
+        let existing: Vec<String> = match match_spec_installed(spec, &vers)? {
+            Some(inst) => sc_library_get_list(Some(inst.name), false)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|x| x.name)
+                .collect(),
+            None => vec![],
+        };
+        for lib in libs {
+            if !existing.contains(lib) {
+                plan.push(SyncAction::Library(spec.to_string(), lib.to_string()));
+            }
+        }
+    }
+
+    if prune {
+        for inst in vers {
+            if !keep.contains(&inst.name) {
+                plan.push(SyncAction::Remove(inst.name));
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+// New installations get the repository settings from `rig add`, see
+// `sync_env()`, the installed ones are updated here.
+
+fn repos_plan(repos: &RepoSettings, ver: &str) -> Result<Vec<SyncAction>, Box<dyn Error>> {
+    let profile = get_system_profile(ver)?;
+    if !profile.exists() {
+        return Ok(vec![]);
+    }
+    let lines = read_lines(&profile)?;
+
+    let wanted = [
+        (RepoSetting::Cran, repos.cran_mirror.as_ref().map(|x| Some(x.to_string()))),
+        (RepoSetting::Rspm, wanted_repo_setting(RepoSetting::Rspm, repos.rspm)?),
+        (RepoSetting::Sysreqs, wanted_repo_setting(RepoSetting::Sysreqs, repos.sysreqs)?),
+    ];
+
+    let mut plan: Vec<SyncAction> = vec![];
+    for (setting, value) in wanted {
+        if let Some(value) = value {
+            if get_repo_setting(&lines, setting)? != value {
+                plan.push(SyncAction::Repos(ver.to_string(), setting, value));
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+// `None` if rig.toml does not have this setting, or `rig add` does not
+// set it up on this platform
+fn wanted_repo_setting(
+    setting: RepoSetting,
+    enabled: Option<bool>,
+) -> Result<Option<Option<String>>, Box<dyn Error>> {
+    match enabled {
+        None => Ok(None),
+        Some(false) => Ok(Some(None)),
+        Some(true) => Ok(default_repo_setting(setting)?.map(Some)),
+    }
+}
+
+// Settings for `rig add`, these are picked up from the environment,
+// see config.rs.
+
+fn sync_env(manifest: &Manifest) {
+    match pak_stream(manifest) {
+        Some(stream) => {
+            std::env::set_var("RIG_ADD_PAK", "true");
+            std::env::set_var("RIG_PAK_VERSION", stream);
+        }
+        None => std::env::set_var("RIG_ADD_PAK", "false"),
+    };
+    if let Some(mirror) = &manifest.repos.cran_mirror {
+        std::env::set_var("RIG_CRAN_MIRROR", mirror);
+    }
+    if let Some(rspm) = manifest.repos.rspm {
+        std::env::set_var("RIG_ADD_RSPM", rspm.to_string());
+    }
+    if let Some(sysreqs) = manifest.repos.sysreqs {
+        std::env::set_var("RIG_ADD_SYSREQS", sysreqs.to_string());
+    }
+}
+
+fn sync_apply(action: &SyncAction) -> Result<(), Box<dyn Error>> {
+    match action {
+        SyncAction::Add(spec) => add_r(&AddOptions::new(spec))?,
+        SyncAction::Alias(alias, spec) => set_alias(&sync_installed(spec)?, alias)?,
+        SyncAction::Default(spec) => sc_set_default(&sync_installed(spec)?)?,
+        SyncAction::Library(spec, lib) => {
+            let ver = sync_installed(spec)?;
+            // These are the user's, also after `sudo`, see library.rs
+            let (main, _) = get_library_path(&ver, false)?;
+            create_user_dir_all(&main)?;
+            library_add(&ver, lib)?;
+        }
+        SyncAction::Pak(spec, stream) => {
+            system_add_pak(Some(vec![sync_installed(spec)?]), stream, false)?
+        }
+        SyncAction::Remove(ver) => rm_r(&[ver.to_string()])?,
+        SyncAction::Repos(ver, setting, value) => {
+            escalate("updating repository settings")?;
+            set_repo_setting(&get_system_profile(ver)?, *setting, value.as_deref())?;
+        }
+    };
+
+    Ok(())
+}
+
+fn sync_installed(spec: &str) -> Result<String, Box<dyn Error>> {
+    let vers = sc_get_list_details()?;
+    match match_spec_installed(spec, &vers)? {
+        Some(inst) => Ok(inst.name),
+        None => rig_bail!(NotInstalled, "R {} is not installed, add it to `versions` in rig.toml", spec),
+    }
+}
+
+fn has_pak(ver: &str) -> bool {
+    match get_library_path(ver, true) {
+        Ok((main, default)) => main.join("pak").exists() || default.join("pak").exists(),
+        Err(_) => false,
+    }
+}
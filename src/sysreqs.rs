@@ -6,6 +6,7 @@ use std::path::Path;
 
 use clap::ArgMatches;
 use lazy_static::lazy_static;
+use serde_derive::Serialize;
 #[cfg(target_os = "macos")]
 use simple_error::*;
 #[cfg(target_os = "macos")]
@@ -17,6 +18,8 @@ use crate::download::*;
 #[cfg(target_os = "macos")]
 use crate::escalate::*;
 #[cfg(target_os = "macos")]
+use crate::output::*;
+#[cfg(target_os = "macos")]
 use crate::run::*;
 #[cfg(target_os = "macos")]
 use crate::utils::*;
@@ -24,7 +27,7 @@ use crate::utils::*;
 #[cfg(target_os = "macos")]
 use crate::macos::*;
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize)]
 pub struct SysReq {
     pub name: String,
     pub description: String
@@ -124,12 +127,9 @@ pub fn sc_sysreqs_info(
         Some(x) => x
     };
 
-    if args.is_present("json") || libargs.is_present("json") || mainargs.is_present("json") {
-        println!("{{");
-        println!("  \"name\": \"{}\",", info.name);
-        println!("  \"description\": \"{}\"", escape_json(&info.description));
-        println!("}}");
-
+    let format = get_output_format(&[args, libargs, mainargs]);
+    if format != OutputFormat::Text {
+        print_output(format, info)?;
     } else {
         let mut tab = Table::new("{:<} {:<}");
         tab.add_row(row!(&info.name, &info.description));
@@ -146,15 +146,13 @@ pub fn sc_sysreqs_list(
     mainargs: &ArgMatches,
 ) -> Result<(), Box<dyn Error>> {
 
-    if args.is_present("json") || libargs.is_present("json") || mainargs.is_present("json") {
-        let num = SYSREQS.len();
-        println!("[");
-        for (idx, sr) in SYSREQS.iter().enumerate() {
-            println!("  {{");
-            println!("    \"name\": \"{}\"", sr);
-            println!("  }}{}", if idx == num - 1 { "" } else { "," });
-        }
-        println!("]");
+    let format = get_output_format(&[args, libargs, mainargs]);
+    if format != OutputFormat::Text {
+        let out: Vec<HashMap<&str, &str>> = SYSREQS
+            .iter()
+            .map(|sr| HashMap::from([("name", *sr)]))
+            .collect();
+        print_output(format, &out)?;
     } else {
         for sr in SYSREQS.iter() {
             println!("{}", sr);
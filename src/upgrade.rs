@@ -0,0 +1,154 @@
+use std::error::Error;
+
+use clap::ArgMatches;
+use semver::Version;
+use simple_error::*;
+use simplelog::*;
+
+#[cfg(target_os = "macos")]
+use crate::macos::*;
+
+#[cfg(target_os = "windows")]
+use crate::windows::*;
+
+#[cfg(target_os = "linux")]
+use crate::linux::*;
+
+use crate::alias::*;
+use crate::common::*;
+use crate::error::*;
+use crate::escalate::*;
+use crate::library::*;
+use crate::project::*;
+use crate::resolve::*;
+use crate::rversion::*;
+use crate::utils::*;
+
+// `rig upgrade` installs the latest patch release of a minor branch, and
+// moves the aliases, the default version and the libraries of the
+// currently installed patch release of that branch to it.
+
+pub fn sc_upgrade(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    escalate("upgrading R versions")?;
+
+    let vers = sc_get_list_details()?;
+    let minors: Vec<String> = if args.is_present("all") {
+        let mut minors: Vec<String> = vers.iter().filter_map(installed_minor).collect();
+        minors.sort();
+        minors.dedup();
+        minors
+    } else {
+        require_with!(args.values_of("minor"), "clap error")
+            .map(|x| x.to_string())
+            .collect()
+    };
+
+    for minor in &minors {
+        let ok = match minor.split_once('.') {
+            Some((x, y)) => x.parse::<u32>().is_ok() && y.parse::<u32>().is_ok(),
+            None => false,
+        };
+        if !ok {
+            rig_bail!(InvalidSpec, "Invalid minor R version: {}, it should be like `4.1`", minor);
+        }
+    }
+
+    for minor in minors {
+        upgrade_minor(&minor, args.is_present("remove-old"))?;
+    }
+
+    Ok(())
+}
+
+// `x.y` of an installed version, if it is a numbered release
+fn installed_minor(inst: &InstalledVersion) -> Option<String> {
+    let ver = inst.version.as_ref()?;
+    let ver = Version::parse(ver).ok()?;
+    if !ver.pre.is_empty() || inst.name == "devel" || inst.name == "next" {
+        return None;
+    }
+    Some(format!("{}.{}", ver.major, ver.minor))
+}
+
+fn installed_patch(inst: &InstalledVersion) -> Option<Version> {
+    Version::parse(inst.version.as_ref()?).ok()
+}
+
+fn upgrade_minor(minor: &str, remove_old: bool) -> Result<(), Box<dyn Error>> {
+    let vers = sc_get_list_details()?;
+    let old = vers
+        .iter()
+        .filter(|x| installed_minor(x).as_deref() == Some(minor))
+        .max_by_key(|x| installed_patch(x));
+    let old = match old {
+        Some(x) => x.to_owned(),
+        None => rig_bail!(NotInstalled, "R {} is not installed, nothing to upgrade", minor),
+    };
+
+    let (os, arch, linux) = parse_platform(&default_platform()?)?;
+    let rver = resolve_versions(vec![minor.to_string()], os, arch, linux)?;
+    let newver = require_with!(rver[0].version.to_owned(), "Cannot resolve R {}", minor);
+
+    if old.version.as_deref() == Some(newver.as_str()) {
+        info!("R {} is up to date: {}", minor, old.name);
+        return Ok(());
+    }
+
+    info!("Upgrading R {} -> {}", old.name, newver);
+    add_r(&AddOptions::new(&newver))?;
+
+    let vers = sc_get_list_details()?;
+    let new = match match_spec_installed(&newver, &vers)? {
+        Some(x) => x,
+        None => bail!("Cannot find the newly installed R {}", newver),
+    };
+
+    for alias in &old.aliases {
+        if !new.aliases.contains(alias) {
+            set_alias(&new.name, alias)?;
+        }
+    }
+
+    if sc_get_default()?.as_ref() == Some(&old.name) {
+        sc_set_default(&new.name)?;
+    }
+
+    carry_libraries(&old.name, &new.name)?;
+
+    // On macOS the new version might have replaced the old one already
+    let old_exists = sc_get_list()?.contains(&old.name);
+    if remove_old && old_exists && old.name != new.name {
+        info!("Removing R {}", old.name);
+        rm_r(&[old.name.to_string()])?;
+    }
+
+    Ok(())
+}
+
+// Patch releases of the same minor branch usually share the user
+// library directory, so the libraries are already there, otherwise we
+// create empty libraries with the same names. We are running as root
+// here, but these are the libraries of the user that called rig, and
+// they also belong to that user.
+
+fn carry_libraries(old: &str, new: &str) -> Result<(), Box<dyn Error>> {
+    let oldlibs = match sc_library_get_list(Some(old.to_string()), false) {
+        Ok(x) => x,
+        Err(_) => return Ok(()),
+    };
+    let (main, _) = get_library_path(new, false)?;
+    create_user_dir_all(&main)?;
+    let newlibs: Vec<String> = sc_library_get_list(Some(new.to_string()), false)?
+        .into_iter()
+        .map(|x| x.name)
+        .collect();
+
+    for lib in oldlibs {
+        if !newlibs.contains(&lib.name) {
+            info!("Adding library {} to R {}", lib.name, new);
+            library_add(new, &lib.name)?;
+        }
+    }
+
+    Ok(())
+}
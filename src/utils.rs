@@ -6,7 +6,6 @@ use std::{file, line};
 
 use regex::Regex;
 
-#[cfg(target_os = "macos")]
 use sha2::{Digest, Sha256};
 
 use simple_error::*;
@@ -106,14 +105,17 @@ pub fn replace_in_file(path: &Path, re: &Regex, sub: &str) -> Result<(), Box<dyn
 }
 
 pub fn append_to_file(path: &Path, extra: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut lines = read_lines(path)?;
+    lines.extend(extra);
+    write_lines(path, &lines)
+}
+
+// Replace the contents of an existing file, keeping its permissions
+pub fn write_lines(path: &Path, lines: &[String]) -> Result<(), Box<dyn Error>> {
     debug!("Updating {:?}", path);
-    let lines = read_lines(path)?;
     let path2 = bak_file(path);
     let mut f = File::create(&path2)?;
-    for line in &lines {
-        write!(f, "{}\n", line)?;
-    }
-    for line in &extra {
+    for line in lines {
         write!(f, "{}\n", line)?;
     }
     let perms = std::fs::metadata(path)?.permissions();
@@ -123,7 +125,6 @@ pub fn append_to_file(path: &Path, extra: Vec<String>) -> Result<(), Box<dyn Err
     Ok(())
 }
 
-#[cfg(target_os = "macos")]
 pub fn calculate_hash(s: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(s);
@@ -132,6 +133,19 @@ pub fn calculate_hash(s: &str) -> String {
     string
 }
 
+pub fn calculate_file_hash(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut file = match File::open(path) {
+        Ok(x) => x,
+        Err(err) => bail!("Cannot open {}: {}", path.display(), err.to_string()),
+    };
+    let mut hasher = Sha256::new();
+    if let Err(err) = std::io::copy(&mut file, &mut hasher) {
+        bail!("Cannot read {}: {}", path.display(), err.to_string());
+    }
+    let hash = hasher.finalize();
+    Ok(format!("{:x}", hash))
+}
+
 pub fn unquote(s: &str) -> String {
     let l = s.len();
     if l <= 2 {
@@ -175,26 +189,6 @@ pub fn read_version_link(path: &str) -> Result<Option<String>, Box<dyn Error>> {
     Ok(Some(fname))
 }
 
-pub fn not_too_old(path: &std::path::PathBuf) -> bool {
-    let meta = std::fs::metadata(path);
-    match meta {
-        Err(_) => return false,
-        Ok(meta) => {
-            let mtime = match meta.modified() {
-                Err(_) => return false,
-                Ok(mtime) => mtime,
-            };
-            let now = std::time::SystemTime::now();
-            let age = match now.duration_since(mtime) {
-                Err(_) => return false,
-                Ok(age) => age,
-            };
-            let day = std::time::Duration::from_secs(60 * 60 * 24);
-            age < day
-        }
-    }
-}
-
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 pub fn get_user() -> Result<User, Box<dyn Error>> {
     let uid: u32;
@@ -233,10 +227,34 @@ pub fn get_user() -> Result<User, Box<dyn Error>> {
     Ok(User { user, uid, gid, dir, sudo })
 }
 
-#[cfg(target_os = "macos")]
-pub fn escape_json(input: &str) -> String {
-    input
-        .replace("\"", "\\\"")
-        .replace("\n", "\\n")
-        .to_string()
+// When rig runs as root via `sudo`, the files it creates in the home
+// directory of the user must belong to the user, not to root.
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub fn chown_to_user(path: &Path) -> Result<(), Box<dyn Error>> {
+    let user = get_user()?;
+    if user.sudo {
+        debug!("Changing owner of {} to {}", path.display(), user.user);
+        nix::unistd::chown(
+            path,
+            Some(nix::unistd::Uid::from_raw(user.uid)),
+            Some(nix::unistd::Gid::from_raw(user.gid)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+pub fn chown_to_user(_path: &Path) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+// Like `create_dir_all()`, but the new directories belong to the user
+pub fn create_user_dir_all(path: &Path) -> Result<(), Box<dyn Error>> {
+    let new: Vec<&Path> = path.ancestors().take_while(|x| !x.exists()).collect();
+    std::fs::create_dir_all(path)?;
+    for dir in new.iter().rev() {
+        chown_to_user(dir)?;
+    }
+    Ok(())
 }
@@ -19,11 +19,14 @@ use winreg::enums::*;
 use winreg::RegKey;
 
 use crate::alias::*;
+use crate::cache::*;
 use crate::common::*;
+use crate::config::*;
 use crate::download::*;
 use crate::escalate::*;
 use crate::library::*;
-use crate::resolve::resolve_versions;
+use crate::repos::*;
+use crate::resolve::{expand_url, resolve_versions};
 use crate::rversion::*;
 use crate::run::*;
 use crate::utils::*;
@@ -32,29 +35,28 @@ pub const R_ROOT: &str = "C:\\Program Files\\R";
 pub const R_VERSIONDIR: &str = "R-{}";
 pub const R_SYSLIBPATH: &str = "R-{}\\library";
 pub const R_BINPATH: &str = "R-{}\\bin\\R.exe";
+const RSPM_URL: &str = "https://packagemanager.rstudio.com/all/latest";
 
 #[warn(unused_variables)]
-pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+pub fn add_r(opts: &AddOptions) -> Result<(), Box<dyn Error>> {
     escalate("adding new R version")?;
-    let alias = get_alias(args);
+    let alias = get_alias(&opts.spec);
     sc_clean_registry()?;
-    let str = args
-        .value_of("str")
-        .ok_or(SimpleError::new("Internal argument error"))?
-        .to_string();
+    let str = opts.spec.to_string();
     if str.len() >= 6 && &str[0..6] == "rtools" {
-        return add_rtools(str);
+        return add_rtools(str, opts);
     }
-    let (_version, target) = download_r(&args)?;
+    let (_version, target) = opts.download()?;
+    let target = target.into_os_string();
     let target_path = Path::new(&target);
 
     info!("Installing {}", target_path.display());
 
     let mut cmd_args = vec![os("/VERYSILENT"), os("/SUPPRESSMSGBOXES")];
-    if args.is_present("without-translations") {
+    if opts.without_translations {
 	cmd_args.push(os("/components=main,x64,i386"));
     }
-    if args.is_present("with-desktop-icon") {
+    if opts.with_desktop_icon {
 	cmd_args.push(os("/mergetasks=desktopicon"));
     } else {
 	cmd_args.push(os("/mergetasks=!desktopicon"));
@@ -89,7 +91,7 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     patch_for_rtools()?;
     maybe_update_registry_default()?;
 
-    if !args.is_present("without-cran-mirror") {
+    if !opts.without("cran-mirror")? {
         match dirname {
             None => {
                 warn!("Cannot set CRAN mirror, cannot determine installation directory");
@@ -100,7 +102,7 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         }
     }
 
-    if !args.is_present("without-rspm") {
+    if !opts.without("rspm")? {
         match dirname {
             None => {
                 warn!("Cannot set up RSPM, cannoe determine installation directory");
@@ -111,7 +113,7 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
         };
     }
 
-    if !args.is_present("without-pak") {
+    if !opts.without("pak")? {
         match dirname {
             None => {
                 warn!("Cannot install pak, cannot determine installation directory");
@@ -119,10 +121,9 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
             Some(ref dirname) => {
                 system_add_pak(
                     Some(vec![dirname.to_string()]),
-                    args.value_of("pak-version")
-                        .ok_or(SimpleError::new("Internal argument error"))?,
+                    &opts.pak_version()?,
                     // If this is specified then we always re-install
-                    args.occurrences_of("pak-version") > 0,
+                    opts.pak_version.is_some(),
                 )?;
             }
         }
@@ -131,14 +132,16 @@ pub fn sc_add(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn add_rtools(version: String) -> Result<(), Box<dyn Error>> {
+fn add_rtools(version: String, opts: &AddOptions) -> Result<(), Box<dyn Error>> {
     let vers;
     if version == "rtools" {
         vers = get_rtools_needed()?;
     } else {
         vers = vec![version.replace("rtools", "")];
     }
-    let client = &reqwest::Client::new();
+    if opts.sha256.is_some() && vers.len() > 1 {
+        bail!("`--sha256` only works when installing a single Rtools version");
+    }
     for ver in vers {
         let rtools42 = &ver[0..2] == "42";
         let rtools4 = &ver[0..1] == "4" || ver == "devel";
@@ -150,21 +153,21 @@ fn add_rtools(version: String) -> Result<(), Box<dyn Error>> {
                 .to_string();
         } else if rtools4 {
             filename = format!("rtools{}-x86_64.exe", ver);
-            url = format!(
-                "https://cloud.r-project.org/bin/windows/Rtools/{}",
-                filename
-            );
+            url = expand_url("{cran}/bin/windows/Rtools/")? + &filename;
         } else {
             filename = format!("Rtools{}.exe", ver);
-            url = format!(
-                "https://cloud.r-project.org/bin/windows/Rtools/{}",
-                filename
-            );
+            url = expand_url("{cran}/bin/windows/Rtools/")? + &filename;
         };
-        let tmp_dir = std::env::temp_dir().join("rig");
-        let target = tmp_dir.join(&filename);
-        info!("Downloading {} -> {}", url, target.display());
-        download_file(client, &url, &target.as_os_str())?;
+        // These are updated in place, so treat them like snapshots
+        let target = cache_download(
+            &url,
+            &filename,
+            Some("rtools".to_string() + &ver),
+            Some("x86_64".to_string()),
+            cache_max_age(&url),
+            opts.sha256.as_deref(),
+            true,
+        )?;
         info!("Installing {}", target.display());
         run(
             target.into_os_string(),
@@ -259,21 +262,18 @@ fn set_cloud_mirror(vers: Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
         Some(x) => x,
         None => sc_get_list()?,
     };
+    let mirror = get_config_string("cran-mirror")?;
 
     info!("Setting default CRAN mirror");
 
     for ver in vers {
         let ver = check_installed(&ver)?;
-        let path = Path::new(R_ROOT).join("R-".to_string() + ver.as_str());
-        let profile = path.join("library/base/R/Rprofile".to_string());
+        let profile = get_system_profile(&ver)?;
         if !profile.exists() {
             continue;
         }
 
-        append_to_file(
-            &profile,
-            vec!["options(repos = c(CRAN = \"https://cloud.r-project.org\"))".to_string()],
-        )?;
+        set_repo_setting(&profile, RepoSetting::Cran, Some(&mirror))?;
     }
 
     Ok(())
@@ -291,31 +291,31 @@ fn set_rspm(vers: Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
         None => sc_get_list()?,
     };
 
-    let rcode = r#"
-options(repos = c(RSPM="https://packagemanager.rstudio.com/all/latest", getOption("repos")))
-"#;
-
     for ver in vers {
         let ver = check_installed(&ver)?;
-        let path = Path::new(R_ROOT).join("R-".to_string() + ver.as_str());
-        let profile = path.join("library/base/R/Rprofile".to_string());
+        let profile = get_system_profile(&ver)?;
         if !profile.exists() {
             continue;
         }
 
-        append_to_file(&profile, vec![rcode.to_string()])?;
+        set_repo_setting(&profile, RepoSetting::Rspm, Some(RSPM_URL))?;
     }
 
     Ok(())
 }
 
-pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+// The value that `rig add` sets up, `None` if it does not set up this
+// setting on this platform
+pub fn default_repo_setting(setting: RepoSetting) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(match setting {
+        RepoSetting::Cran => Some(get_config_string("cran-mirror")?),
+        RepoSetting::Rspm if std::env::consts::ARCH == "x86_64" => Some(RSPM_URL.to_string()),
+        _ => None,
+    })
+}
+
+pub fn rm_r(vers: &[String]) -> Result<(), Box<dyn Error>> {
     escalate("removing R versions")?;
-    let vers = args.values_of("version");
-    if vers.is_none() {
-        return Ok(());
-    }
-    let vers = vers.ok_or(SimpleError::new("Internal argument error"))?;
     let default = sc_get_default()?;
 
     for ver in vers {
@@ -337,11 +337,12 @@ pub fn sc_rm(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
             }
         }
 
-        let ver = "R-".to_string() + &ver;
+        let dirname = "R-".to_string() + &ver;
         let dir = Path::new(R_ROOT);
-        let dir = dir.join(ver);
+        let dir = dir.join(dirname);
         info!("Removing {}", dir.display());
         remove_dir_all(&dir)?;
+        remove_version_aliases(&ver)?;
     }
 
     sc_clean_registry()?;
@@ -386,58 +387,78 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
         "Rscript.bat".to_string(),
     ];
 
+    let tools = get_quick_links()?;
+
     std::fs::create_dir_all(bin)?;
 
     for ver in vers {
-        let filename = "R-".to_string() + &ver + ".bat";
-        let linkfile = base.join("bin").join(&filename);
-        new_links.push(filename);
         let target = base.join("R-".to_string() + &ver);
-
-        let cnt = "@\"C:\\Program Files\\R\\R-".to_string() + &ver + "\\bin\\R\" %*\n";
-        let op;
-        if linkfile.exists() {
-            op = "Updating";
-            let orig = std::fs::read_to_string(&linkfile)?;
-            if orig == cnt {
+        for tool in &tools {
+            if !target.join("bin").join(tool.to_owned() + ".exe").exists() {
+                debug!("No {} in R {}, no quick link", tool, ver);
                 continue;
             }
-        } else {
-            op = "Adding";
-        };
-        info!("{} R-{} -> {}", op, ver, target.display());
-        let mut file = File::create(&linkfile)?;
-        file.write_all(cnt.as_bytes())?;
+            let filename = tool.to_owned() + "-" + &ver + ".bat";
+            let linkfile = base.join("bin").join(&filename);
+            new_links.push(filename);
+
+            let cnt = quick_link_bat(&ver, tool);
+            let op;
+            if linkfile.exists() {
+                op = "Updating";
+                let orig = std::fs::read_to_string(&linkfile)?;
+                if orig == cnt {
+                    continue;
+                }
+            } else {
+                op = "Adding";
+            };
+            info!("{} {}-{} -> {}", op, tool, ver, target.display());
+            let mut file = File::create(&linkfile)?;
+            file.write_all(cnt.as_bytes())?;
+        }
     }
 
-    // Delete the ones we don't need
-    let re_als = re_alias();
+    // Delete the ones we don't need. Alias links, including the user
+    // defined ones, are kept as long as their R version is installed.
+    // Links of tools that are not configured any more are deleted once
+    // their R version is removed, like on the other platforms.
+    let mut aliases: Vec<String> = vec!["oldrel".to_string(), "release".to_string(), "next".to_string()];
+    aliases.extend(get_config_aliases()?.keys().map(|x| regex::escape(x)));
+    let re_tools: Vec<String> = tools.iter().map(|x| regex::escape(x)).collect();
+    let re_als = Regex::new(
+        &("^(".to_string() + &re_tools.join("|") + ")-(" + &aliases.join("|") + ")[.]bat$")
+    )?;
+    let re_link = Regex::new("^[A-Za-z0-9_]+-.+[.]bat$")?;
     let old_links = std::fs::read_dir(base.join("bin"))?;
     for path in old_links {
         let path = path?;
         match path.file_name().into_string() {
             Err(_) => continue,
             Ok(filename) => {
-                if !filename.ends_with(".bat") {
+                if !re_link.is_match(&filename) || new_links.contains(&filename) {
                     continue;
                 }
-                if !filename.starts_with("R-") {
-                    continue;
+                let tool = tools.iter().find(|t| filename.starts_with(&(t.to_string() + "-")));
+                if tool.is_none() || re_als.is_match(&filename) {
+                    let rver = match find_r_version_in_link(&path.path()) {
+                        Ok(x) => x,
+                        // Not a link that rig created, leave it alone
+                        Err(_) => continue,
+                    };
+                    let keep = match tool {
+                        Some(tool) => new_links.contains(&(tool.to_owned() + "-" + &rver + ".bat")),
+                        None => base.join("R-".to_string() + &rver).exists(),
+                    };
+                    if keep {
+                        continue;
+                    }
                 }
-		if re_als.is_match(&filename) {
-		    let rver = find_r_version_in_link(&path.path())?;
-		    let realname = "R-".to_string() + &rver + ".bat";
-		    if new_links.contains(&realname) {
-			continue;
-		    }
-		}
-                if !new_links.contains(&filename) {
-                    info!("Deleting unused {}", filename);
-                    match std::fs::remove_file(path.path()) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            warn!("Failed to remove {}: {}", filename, e.to_string());
-                        }
+                info!("Deleting unused {}", filename);
+                match std::fs::remove_file(path.path()) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Failed to remove {}: {}", filename, e.to_string());
                     }
                 }
             }
@@ -447,6 +468,10 @@ pub fn sc_system_make_links() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+pub fn quick_link_bat(ver: &str, tool: &str) -> String {
+    "@\"C:\\Program Files\\R\\R-".to_string() + ver + "\\bin\\" + tool + "\" %*\n"
+}
+
 fn re_alias() -> Regex {
     let re = Regex::new("^R-(oldrel|release|next)[.]bat$").unwrap();
     re
@@ -544,14 +569,20 @@ pub fn sc_system_no_openmp(_args: &ArgMatches) -> Result<(), Box<dyn Error>> {
 }
 
 pub fn get_resolve(args: &ArgMatches) -> Result<Rversion, Box<dyn Error>> {
-    let str = args
-        .value_of("str")
+    Ok(get_resolve_all(args)?[0].to_owned())
+}
+
+pub fn get_resolve_all(args: &ArgMatches) -> Result<Vec<Rversion>, Box<dyn Error>> {
+    let eps: Vec<String> = args
+        .values_of("str")
         .ok_or(SimpleError::new("Internal argument error"))?
-        .to_string();
+        .map(|x| x.to_string())
+        .collect();
+    get_resolve_specs(eps)
+}
 
-    let eps = vec![str];
-    let version = resolve_versions(eps, "win".to_string(), "default".to_string(), None)?;
-    Ok(version[0].to_owned())
+pub fn get_resolve_specs(eps: Vec<String>) -> Result<Vec<Rversion>, Box<dyn Error>> {
+    resolve_versions(eps, "win".to_string(), "default".to_string(), None)
 }
 
 // ------------------------------------------------------------------------
@@ -894,6 +925,19 @@ pub fn get_system_profile(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     Ok(profile)
 }
 
+pub fn get_r_root() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(PathBuf::from(R_ROOT))
+}
+
+// The R installer decides where R goes, so there is only one root here
+pub fn get_r_roots() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    Ok(vec![get_r_root()?])
+}
+
+pub fn get_r_version_root(_rver: &str) -> Result<PathBuf, Box<dyn Error>> {
+    get_r_root()
+}
+
 pub fn get_r_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     debug!("Finding R {} binary", rver);
     let base = Path::new(R_ROOT);
@@ -905,6 +949,15 @@ pub fn get_r_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
     Ok(bin)
 }
 
+pub fn get_rscript_binary(rver: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let bin = Path::new(R_ROOT)
+        .join("R-".to_string() + rver)
+        .join("bin")
+        .join("Rscript.exe");
+    debug!("R {} Rscript: {}", rver, bin.display());
+    Ok(bin)
+}
+
 pub fn check_has_pak(_rver: &str) -> Result<(), Box<dyn Error>> {
     // TODO: actually check. Right now the install will fail
     Ok(())
@@ -100,10 +100,16 @@ teardown() {
         run rig ls
         echo "$output" | grep -q "^[* ] 3[.]3[.]3"
     fi
+    run rig alias add old 3.3.3
+    [[ "$status" -eq 0 ]]
     run rig rm 3.3.3
     [[ "$status" -eq 0 ]]
     run rig list
     echo $output | grep -vq "^[* ] 3.3.3"
+    # the aliases of the removed version are gone, too
+    [[ ! -e /usr/local/bin/R-old ]]
+    run rig alias list
+    ! echo "$output" | grep -q "^old "
 }
 
 @test "system create-lib" {
@@ -145,3 +151,444 @@ teardown() {
     uid=`stat -c "%u" "$libdir"`
     [[ "$uid" -eq "`id -u`" ]]
 }
+
+@test "system distros" {
+    run rig system distros
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^current  *distro"
+    echo "$output" | grep -q "^[*]  *[a-z]"
+    run rig system distros --json
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q '"current": true'
+}
+
+@test "mirror" {
+    mirror=`mktemp -d`
+    run rig mirror sync --dir "$mirror" 4.1.2
+    [[ "$status" -eq 0 ]]
+    ls "$mirror"/api.r-hub.io/rversions/r-versions
+    run env RIG_MIRROR="$mirror" rig resolve 4.1.2
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^4[.]1[.]2 "
+    run env RIG_MIRROR="$mirror" rig resolve 4.1.1
+    [[ "$status" -eq 0 ]]
+    run env RIG_MIRROR=/nonexistent rig resolve 4.1.2
+    [[ "$status" -ne 0 ]]
+    rm -rf "$mirror"
+}
+
+@test "download checksums" {
+    stand=`mktemp -d`
+    mkdir -p "$stand/api" "$stand/cdn"
+    echo "not really an R installer" > "$stand/cdn/r-4.2.1.deb"
+    url=http://127.0.0.1:8977/cdn/r-4.2.1.deb
+    sha=`sha256sum "$stand/cdn/r-4.2.1.deb" | cut -d" " -f1`
+    echo '{"version":1,"distros":[{"distro":"test","version":"1","installer":"deb","url":{"x86_64":"{cdn}/r-{}.deb","aarch64":"{cdn}/r-{}.deb"}}]}' > "$stand/distros.json"
+    python3 -m http.server 8977 --bind 127.0.0.1 --directory "$stand" &
+    server=$!
+    sleep 1
+    export RIG_DISTRO_MANIFEST="$stand/distros.json"
+    export RIG_API_URL=http://127.0.0.1:8977/api
+    export RIG_CDN_URL=http://127.0.0.1:8977/cdn
+    # digest from the rversions API
+    echo "{\"version\": \"4.2.1\", \"URL\": \"$url\", \"sha256\": \"$sha\"}" > "$stand/api/r-release"
+    RIG_CACHE_DIR="$stand/cache1" run rig cache prefetch --platform linux-test-1 release
+    [[ "$status" -eq 0 ]]
+    # wrong digest
+    echo "{\"version\": \"4.2.1\", \"URL\": \"$url\", \"sha256\": \"${sha//[0-9]/0}\"}" > "$stand/api/r-release"
+    RIG_CACHE_DIR="$stand/cache2" run rig cache prefetch --platform linux-test-1 release
+    [[ "$status" -ne 0 ]]
+    echo "$output" | grep -q "checksum mismatch"
+    [[ -z "`ls $stand/cache2 | grep r-4.2.1`" ]]
+    # no digest, only a warning by default
+    echo '{"version": "4.2.1"}' > "$stand/api/r-release"
+    RIG_CACHE_DIR="$stand/cache3" run rig cache prefetch --platform linux-test-1 release
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "No checksum is available"
+    RIG_VERIFY_DOWNLOAD=true RIG_CACHE_DIR="$stand/cache5" run rig cache prefetch --platform linux-test-1 release
+    [[ "$status" -ne 0 ]]
+    echo "$output" | grep -q "No SHA-256 checksum"
+    # sidecar checksum file
+    echo "$sha  r-4.2.1.deb" > "$stand/cdn/r-4.2.1.deb.sha256"
+    RIG_VERIFY_DOWNLOAD=true RIG_CACHE_DIR="$stand/cache4" run rig cache prefetch --platform linux-test-1 release
+    [[ "$status" -eq 0 ]]
+    kill $server
+    rm -rf "$stand"
+    # invalid digests are caught before downloading anything
+    run rig add --sha256 foobar 4.1.2
+    [[ "$status" -eq 14 ]]
+    echo "$output" | grep -q "Invalid SHA-256"
+}
+
+@test "download timeout" {
+    stand=`mktemp -d`
+    mkdir -p "$stand/api"
+    # sends the first couple of bytes of the installers, then stalls
+    cat > "$stand/stall.py" <<'EOF'
+import functools, http.server, sys, time
+class Handler(http.server.SimpleHTTPRequestHandler):
+    def do_GET(self):
+        if not self.path.startswith("/cdn/"):
+            return super().do_GET()
+        self.send_response(200)
+        self.send_header("Content-Length", "1000")
+        self.end_headers()
+        self.wfile.write(b"x" * 10)
+        self.wfile.flush()
+        time.sleep(600)
+handler = functools.partial(Handler, directory=sys.argv[2])
+http.server.ThreadingHTTPServer(("127.0.0.1", int(sys.argv[1])), handler).serve_forever()
+EOF
+    echo '{"version":1,"distros":[{"distro":"test","version":"1","installer":"deb","url":{"x86_64":"{cdn}/r-{}.deb","aarch64":"{cdn}/r-{}.deb"}}]}' > "$stand/distros.json"
+    echo '{"version": "4.2.1", "URL": "x"}' > "$stand/api/r-release"
+    python3 "$stand/stall.py" 8976 "$stand" &
+    server=$!
+    sleep 1
+    export RIG_DISTRO_MANIFEST="$stand/distros.json"
+    export RIG_API_URL=http://127.0.0.1:8976/api
+    export RIG_CDN_URL=http://127.0.0.1:8976/cdn
+    export RIG_CACHE_DIR="$stand/cache"
+    RIG_DOWNLOAD_TIMEOUT=1 RIG_DOWNLOAD_RETRIES=1 run timeout 60 rig cache prefetch --platform linux-test-1 release
+    kill $server
+    rm -rf "$stand"
+    [[ "$status" -ne 0 ]]
+    [[ "$status" -ne 124 ]]
+    echo "$output" | grep -q "stalled, no data in 1s, retrying"
+    echo "$output" | grep -q "ERROR.*stalled, no data in 1s$"
+}
+
+@test "cache" {
+    # Not root's cache, that `sudo rig add` uses, see `rig cache --help`
+    export RIG_CACHE_DIR=`mktemp -d`
+    run rig cache prefetch 4.1.2
+    [[ "$status" -eq 0 ]]
+    run rig cache list
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^4[.]1[.]2 "
+    run rig cache list --json
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q '"version": "4.1.2"'
+    run rig cache clean
+    [[ "$status" -eq 0 ]]
+    run rig cache list
+    [[ "$status" -eq 0 ]]
+    ! echo "$output" | grep -q "^4[.]1[.]2 "
+    rm -rf "$RIG_CACHE_DIR"
+}
+
+@test "project pin" {
+    prj=`mktemp -d`
+    mkdir "$prj/sub"
+    echo "4.1.2" > "$prj/.rig-version"
+    cd "$prj/sub"
+    run rig which
+    [[ "$status" -eq 0 ]]
+    [[ "$output" = "/opt/R/4.1.2/bin/R" ]]
+    # an explicit minor version matches like a pin
+    run rig which 4.1
+    [[ "$status" -eq 0 ]]
+    [[ "$output" = "/opt/R/4.1.2/bin/R" ]]
+    run rig ls
+    echo "$output" | grep -q "^[* ] 4[.]1[.]2 .*(pinned)"
+    run sudo rig add --from-project
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "already installed"
+    run rig add --from-project 4.1.2
+    [[ "$status" -ne 0 ]]
+    echo "$output" | grep -q "cannot be used with"
+    # a broken pin is only a warning for `rig ls`
+    : > "$prj/.rig-version"
+    run rig ls
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "No R version in"
+    cd /
+    rm -rf "$prj"
+}
+
+@test "run" {
+    run rig run 4.1 -- -q -s -e 'cat(as.character(getRversion()))'
+    [[ "$status" -eq 0 ]]
+    [[ "$output" =~ ^4[.]1[.] ]]
+    run rig run --rscript release -- -e 'quit(status = 7)'
+    [[ "$status" -eq 7 ]]
+    run rig run 3.0
+    [[ "$status" -ne 0 ]]
+}
+
+@test "shell-init and use" {
+    run bash -c 'eval "$(rig shell-init bash)"; rig use 4.1; which R; echo "R_HOME=$R_HOME"; echo "$RIG_USE_VERSION"'
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^/opt/R/4[.]1[.][0-9]*/bin/R$"
+    echo "$output" | grep -q "^R_HOME=$"
+    echo "$output" | grep -q "^4[.]1[.][0-9]*$"
+    run rig use 4.1
+    [[ "$status" -ne 0 ]]
+    echo "$output" | grep -q "shell integration"
+}
+
+@test "quick links" {
+    run sudo rig system make-links
+    [[ "$status" -eq 0 ]]
+    run ls /usr/local/bin
+    echo "$output" | grep -q "^R-4[.]1[.]"
+    echo "$output" | grep -q "^Rscript-4[.]1[.]"
+    echo "$output" | grep -q "^Rscript-release$"
+    run Rscript-release -e 'cat("ok")'
+    [[ "$output" = "ok" ]]
+}
+
+@test "user install" {
+    if ! command -v dpkg-deb; then skip; fi
+    export HOME=`mktemp -d`
+    run rig add --user 4.1 --without-pak
+    [[ "$status" -eq 0 ]]
+    run rig list --user
+    echo "$output" | grep -q "4[.]1[.]"
+    run "$HOME/.local/bin/Rscript" -e 'cat(R.home())'
+    [[ "$output" =~ ^$HOME/.local/share/rig/R/4[.]1[.] ]]
+    run rig rm --user 4.1
+    [[ "$status" -eq 0 ]]
+    rm -rf "$HOME"
+}
+
+@test "custom R root" {
+    if ! command -v dpkg-deb; then skip; fi
+    root=`mktemp -d`
+    export RIG_R_ROOT="$root:/opt/R"
+    run sudo -E rig add 4.1 --without-pak
+    [[ "$status" -eq 0 ]]
+    run rig list --json
+    echo "$output" | grep -q "\"root\": \"$root\""
+    echo "$output" | grep -q '"root": "/opt/R"'
+    run rig run 4.1 -- -q -s -e 'cat(R.home())'
+    [[ "$output" =~ ^$root/4[.]1[.] ]]
+    run sudo -E rig rm 4.1
+    [[ "$status" -eq 0 ]]
+    sudo rm -rf "$root"
+}
+
+@test "config" {
+    export HOME=`mktemp -d`
+    run rig config set add-pak false
+    [[ "$status" -eq 0 ]]
+    run rig config get add-pak
+    [[ "$output" = "false" ]]
+    run rig config list
+    echo "$output" | grep -q "^add-pak  *false  *user"
+    run rig config set add-pak maybe
+    [[ "$status" -ne 0 ]]
+    run rig config unset add-pak
+    run rig config get add-pak
+    [[ "$output" = "true" ]]
+    RIG_ADD_PAK=false run rig config get add-pak
+    [[ "$output" = "false" ]]
+    rm -rf "$HOME"
+}
+
+@test "sync" {
+    if ! rig list | grep -q "4[.]1[.]"; then
+        run sudo rig add 4.1 --without-pak
+        [[ "$status" -eq 0 ]]
+    fi
+    ver=`rig list --format tsv | grep "^4[.]1[.]" | head -1 | cut -f1`
+    sudo rig system repos set --cran https://cloud.r-project.org "$ver"
+    if rig list | grep -q "3[.]5[.]3"; then
+        sudo rig rm 3.5.3
+    fi
+    dir=`mktemp -d`
+    cd "$dir"
+    echo 'versions = ["4.1", "3.5.3"]' > rig.toml
+    echo 'pak = false' >> rig.toml
+    run rig sync --dry-run
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "add R 3.5.3"
+    echo 'versions = ["4.1"]' > rig.toml
+    echo 'pak = false' >> rig.toml
+    echo '[libraries]' >> rig.toml
+    echo '"4.1" = ["synctest"]' >> rig.toml
+    echo '[repos]' >> rig.toml
+    echo 'cran-mirror = "https://cran.rstudio.com"' >> rig.toml
+    run rig sync --dry-run
+    echo "$output" | grep -q "set CRAN of R $ver to https://cran.rstudio.com"
+    echo "$output" | grep -q "add library synctest for R 4.1"
+    run rig sync
+    [[ "$status" -eq 0 ]]
+    run rig sync --dry-run
+    echo "$output" | grep -q "up to date"
+    run rig system repos show --format tsv "$ver"
+    echo "$output" | grep -q "https://cran.rstudio.com"
+    # the library is the user's, not root's
+    [[ -z "`find ~/R -user root`" ]]
+    find ~/R -name __synctest -prune -exec rm -rf {} +
+    sudo rig system repos set --cran https://cloud.r-project.org "$ver"
+    echo 'versions = ["4.1", "devel"' > rig.toml
+    run rig sync --dry-run
+    [[ "$status" -ne 0 ]]
+    cd
+    rm -rf "$dir"
+}
+
+@test "add multiple versions" {
+    if ! rig list | grep -q "4[.]0[.]"; then
+        run sudo rig add 4.0 4.1 --without-pak
+        [[ "$status" -eq 0 ]]
+        echo "$output" | grep -q "^4[.]0  *ok"
+        echo "$output" | grep -q "^4[.]1  *ok"
+    fi
+    run sudo rig add 4.1 3.0.0.0 --without-pak
+    [[ "$status" -ne 0 ]]
+    echo "$output" | grep -q "^3[.]0[.]0[.]0  *failed"
+    echo "$output" | grep -q "^4[.]1  *ok"
+    run sudo rig rm 4.0
+    [[ "$status" -eq 0 ]]
+}
+
+@test "upgrade" {
+    if ! rig list | grep -q "4[.]1[.]2"; then
+        run sudo rig add 4.1.2 --without-pak
+        [[ "$status" -eq 0 ]]
+    fi
+    sudo rig default 4.1.2
+    rig library add upgradetest
+    run sudo rig upgrade 4.1 --remove-old
+    [[ "$status" -eq 0 ]]
+    run rig default
+    [[ ! "$output" =~ 4[.]1[.]2 ]]
+    [[ "$output" =~ 4[.]1[.] ]]
+    # the libraries are the user's, not root's
+    run rig library list
+    echo "$output" | grep -q upgradetest
+    [[ -z "`find ~/R ~/.local/share/rig -user root`" ]]
+    rig library rm upgradetest
+    run rig list
+    [[ ! "$output" =~ 4[.]1[.]2 ]]
+    run sudo rig upgrade 4.1
+    echo "$output" | grep -q "up to date"
+    run rig upgrade 4.x
+    [[ "$status" -ne 0 ]]
+}
+
+@test "output formats" {
+    run rig list --format tsv
+    [[ "$status" -eq 0 ]]
+    [[ "${lines[0]}" =~ ^name.version.path.binary.aliases.root.default.pinned$ ]]
+    run rig --format yaml list
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^- name: "
+    run rig list --json
+    echo "$output" | python3 -m json.tool
+    run rig config list --format json
+    echo "$output" | python3 -c 'import json,sys; assert "key" in json.load(sys.stdin)[0]'
+    run rig list --format xml
+    [[ "$status" -ne 0 ]]
+}
+
+@test "exit status and JSON errors" {
+    run rig default 9.9.9
+    [[ "$status" -eq 10 ]]
+    run rig resolve foobar
+    [[ "$status" -eq 14 ]]
+    run rig default 9.9.9 --json
+    [[ "$status" -eq 10 ]]
+    echo "$output" | python3 -c 'import json,sys; e = json.load(sys.stdin); assert e["error"] == "not-installed" and e["exit_code"] == 10'
+}
+
+@test "doctor" {
+    if ! rig list | grep -q "4[.]1[.]"; then
+        run sudo rig add 4.1 --without-pak
+        [[ "$status" -eq 0 ]]
+    fi
+    ver=`rig list --format tsv | grep "^4[.]1[.]" | head -1 | cut -f1`
+    profile="/opt/R/$ver/lib/R/library/base/R/Rprofile"
+    printf '## rig R_LIBS_USER start\n## rig R_LIBS_USER end\n' | sudo tee -a "$profile"
+    sudo ln -s "/opt/R/9.9.9/bin/R" /usr/local/bin/R-9.9.9
+    # a stale cached library in the config of the user
+    config=~/.local/share/rig/config.json
+    mkdir -p `dirname "$config"`
+    [[ -f "$config" ]] || echo '{}' > "$config"
+    python3 -c 'import json,sys; c = json.load(open(sys.argv[1])); c.setdefault("userlibrary", {})["9.9.9"] = "/tmp/9.9"; json.dump(c, open(sys.argv[1], "w"))' "$config"
+    run rig doctor
+    [[ "$status" -eq 1 ]]
+    echo "$output" | grep -q "R_LIBS_USER\|markers"
+    echo "$output" | grep -q "R-9.9.9"
+    echo "$output" | grep -q "Cached user library for R 9.9.9"
+    run rig doctor --fix
+    [[ "$status" -eq 0 ]]
+    [[ ! -L /usr/local/bin/R-9.9.9 ]]
+    ! grep -q "9[.]9[.]9" "$config"
+    [[ -O "$config" ]]
+    run grep -c "## rig R_LIBS_USER start" "$profile"
+    [[ "$output" = "1" ]]
+    run rig doctor
+    [[ "$status" -eq 0 ]]
+}
+
+@test "alias" {
+    if ! rig list | grep -q "4[.]1[.]"; then
+        run sudo rig add 4.1 --without-pak
+        [[ "$status" -eq 0 ]]
+    fi
+    ver=`rig list --format tsv | grep "^4[.]1[.]" | head -1 | cut -f1`
+    run sudo rig alias add prod "$ver"
+    [[ "$status" -eq 0 ]]
+    [[ -L /usr/local/bin/R-prod ]]
+    run rig alias list
+    echo "$output" | grep -q "prod *$ver"
+    run rig run prod -- -q -s -e 'cat(as.character(getRversion()))'
+    [[ "$output" = "$ver" ]]
+    run sudo rig alias add 1bad "$ver"
+    [[ "$status" -ne 0 ]]
+    run sudo rig alias rm prod
+    [[ "$status" -eq 0 ]]
+    [[ ! -e /usr/local/bin/R-prod ]]
+    run rig run prod -- --version
+    [[ "$status" -ne 0 ]]
+}
+
+@test "system repos" {
+    if ! rig list | grep -q "4[.]1[.]"; then
+        run sudo rig add 4.1 --without-pak
+        [[ "$status" -eq 0 ]]
+    fi
+    ver=`rig list --format tsv | grep "^4[.]1[.]" | head -1 | cut -f1`
+    profile="/opt/R/$ver/lib/R/library/base/R/Rprofile"
+    run sudo rig system repos set --cran https://cran.rstudio.com "$ver"
+    [[ "$status" -eq 0 ]]
+    run sudo rig system repos set --cran https://cran.rstudio.com "$ver"
+    [[ "$status" -eq 0 ]]
+    run grep -c "## rig CRAN start" "$profile"
+    [[ "$output" = "1" ]]
+    run rig system repos show --format tsv "$ver"
+    echo "$output" | grep -q "https://cran.rstudio.com"
+    run rig run "$ver" -- -q -s -e 'cat(getOption("repos")[["CRAN"]])'
+    [[ "$output" = "https://cran.rstudio.com" ]]
+    run sudo rig system repos unset --cran --rspm "$ver"
+    [[ "$status" -eq 0 ]]
+    run grep -c "## rig CRAN start\|## rig RSPM start" "$profile"
+    [[ "$output" = "0" ]]
+    run sudo rig system repos set --cran https://cloud.r-project.org "$ver"
+    [[ "$status" -eq 0 ]]
+}
+
+@test "base URLs" {
+    stand=`mktemp -d`
+    mkdir -p "$stand/api" "$stand/svn/R/trunk"
+    echo '{"version": "4.2.1"}' > "$stand/api/r-release"
+    echo '4.3.0 Under development (unstable)' > "$stand/svn/R/trunk/VERSION"
+    python3 -m http.server 8976 --bind 127.0.0.1 --directory "$stand" &
+    server=$!
+    sleep 1
+    export RIG_API_URL=http://127.0.0.1:8976/api
+    export RIG_SVN_URL=http://127.0.0.1:8976/svn
+    export RIG_CDN_URL=http://cdn.example.com
+    run rig resolve release
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^4[.]2[.]1 http://cdn[.]example[.]com/"
+    run rig resolve devel
+    [[ "$status" -eq 0 ]]
+    echo "$output" | grep -q "^4[.]3[.]0 http://cdn[.]example[.]com/"
+    run rig config get svn-url
+    [[ "$output" = "http://127.0.0.1:8976/svn" ]]
+    kill $server
+    rm -rf "$stand"
+}
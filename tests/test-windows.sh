@@ -210,8 +210,27 @@ teardown() {
     [[ "$status" -eq 0 ]]
 }
 
-# This is tested implicitly
+# This is tested implicitly, except for user defined aliases
 
 @test "system make-links" {
-    true
+    run rig alias add prod 4.1.1
+    [[ "$status" -eq 0 ]]
+    run rig system make-links
+    [[ "$status" -eq 0 ]]
+    [[ -e "/mnt/c/Program Files/R/bin/R-prod.bat" ||
+       -e "C:/Program Files/R/bin/R-prod.bat" ]]
+    run rig alias rm prod
+    [[ "$status" -eq 0 ]]
+}
+
+@test "system make-links, links of other tools" {
+    bin="C:/Program Files/R/bin"
+    if [[ ! -d "$bin" ]]; then bin="/mnt/c/Program Files/R/bin"; fi
+    printf '@"C:\\Program Files\\R\\R-9.9.9\\bin\\Rgui" %%*\n' > "$bin/Rgui-9.9.9.bat"
+    printf '@"C:\\Program Files\\R\\R-4.1.1\\bin\\Rgui" %%*\n' > "$bin/Rgui-4.1.1.bat"
+    run rig system make-links
+    [[ "$status" -eq 0 ]]
+    [[ ! -e "$bin/Rgui-9.9.9.bat" ]]
+    [[ -e "$bin/Rgui-4.1.1.bat" ]]
+    rm -f "$bin/Rgui-4.1.1.bat"
 }